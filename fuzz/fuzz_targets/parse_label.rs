@@ -0,0 +1,19 @@
+#![no_main]
+#![allow(dead_code)]
+
+// A name anywhere in a message, the first byte picks where it starts. Compression pointers may
+// lead to any earlier part of the message.
+
+#[path = "../../src/message/mod.rs"]
+mod message;
+
+use libfuzzer_sys::fuzz_target;
+use message::wire::Wire;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&start, msg)) = data.split_first() else {
+        return;
+    };
+    let wire = Wire::new(msg);
+    let _ = wire.name(wire.at(start as usize));
+});
@@ -0,0 +1,24 @@
+#![no_main]
+#![allow(dead_code)]
+
+// The server's request path: the packet is validated in place, read through the lazy view and
+// only then turned into an owned message.
+
+#[path = "../../src/message/mod.rs"]
+mod message;
+
+use libfuzzer_sys::fuzz_target;
+use message::message_ref::MessageRef;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(request) = MessageRef::parse(data) else {
+        return;
+    };
+    for question in request.questions() {
+        let _ = question.to_question();
+    }
+    for record in request.answers().chain(request.authority()).chain(request.additional()) {
+        let _ = record.to_answer();
+    }
+    let _ = request.to_message();
+});
@@ -0,0 +1,18 @@
+#![no_main]
+#![allow(dead_code)]
+
+// Anything that parses has to come out the same after being written and parsed again.
+
+#[path = "../../src/message/mod.rs"]
+mod message;
+
+use libfuzzer_sys::fuzz_target;
+use message::message::{AsBytes, Message};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(response) = Message::parse_resolver_response(data) else {
+        return;
+    };
+    let reparsed = Message::parse_resolver_response(&response.as_bytes());
+    assert_eq!(reparsed, Ok(response));
+});
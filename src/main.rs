@@ -1,11 +1,16 @@
 mod message;
+mod server;
 
-use crate::message::message::{AsBytes, Message};
-use anyhow::{Context, Result};
-use clap::command;
+use crate::server::{Server, WhenBusy};
+use clap::builder::RangedU64ValueParser;
 use clap::Parser;
-use log::{debug, error, info};
-use tokio::{self, net::UdpSocket};
+use log::{debug, info};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinSet;
 
 #[derive(Parser, Debug)]
 #[command(name = "DNS Forwarder")]
@@ -14,6 +19,73 @@ use tokio::{self, net::UdpSocket};
 struct Args {
     #[arg(short, long)]
     resolver: String,
+
+    #[arg(
+        short,
+        long = "listen",
+        value_name = "ADDRESS:PORT",
+        default_value = "127.0.0.1:2053",
+        help = "Address to listen on, may be repeated. IPv6 addresses are written as [::1]:53"
+    )]
+    listen: Vec<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1024,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Most queries answered at the same time"
+    )]
+    max_in_flight: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = WhenBusy::Drop,
+        help = "What to do with queries beyond --max-in-flight"
+    )]
+    when_busy: WhenBusy,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 256,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Most TCP connections open at the same time"
+    )]
+    max_connections: usize,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 10,
+        help = "How long a TCP connection may sit without a query before it is closed"
+    )]
+    tcp_idle_timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        default_value_t = 1000,
+        help = "How long to wait for the resolver before asking again, doubled after every try"
+    )]
+    upstream_timeout: u64,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        help = "How often to ask the resolver again when it doesn't answer"
+    )]
+    upstream_retries: u32,
+
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        default_value_t = 5000,
+        help = "Longest a query may take upstream before the client gets SERVFAIL"
+    )]
+    upstream_deadline: u64,
 }
 
 #[tokio::main]
@@ -22,77 +94,51 @@ async fn main() {
     let args = Args::parse();
     debug!("Main started with args: {:?}", args);
 
-    let udp_socket = UdpSocket::bind("127.0.0.1:2053")
+    let sockets: Vec<(UdpSocket, TcpListener)> = args
+        .listen
+        .iter()
+        .map(|&addr| {
+            let sockets = bind_udp(addr)
+                .and_then(|udp| Ok((udp, bind_tcp(addr)?)))
+                .unwrap_or_else(|e| panic!("Failed to bind to {}: {}", addr, e));
+            info!("DNS server listening on {} (UDP and TCP)", addr);
+            sockets
+        })
+        .collect();
+
+    let server = Server::new(args)
         .await
-        .expect("Failed to bind to address");
-    info!("DNS server started on 127.0.0.1:2053");
-    let mut buf = [0; 512];
-
-    loop {
-        match udp_socket.recv_from(&mut buf).await {
-            Ok((size, source)) => {
-                debug!(
-                    "Received {} bytes from {}: {:?}",
-                    size,
-                    source,
-                    &buf[..size]
-                );
-
-                let request = Message::parse_request(&buf);
-                let response = if !args.resolver.is_empty() {
-                    info!("Querying resolver");
-                    match resolve_query(&args.resolver, request).await {
-                        Ok(r) => r,
-                        Err(e) => panic!("{}", e),
-                    }
-                } else {
-                    info!("Creating local response.");
-                    request.create_response().as_bytes()
-                };
-                match udp_socket.send_to(&response, source).await {
-                    Ok(bytes_sent) => {
-                        debug!("Sent {} bytes in response to {}", bytes_sent, source)
-                    }
-                    Err(e) => error!("Failed to send response: {}", e),
-                }
-            }
-            Err(e) => {
-                error!("Error receiving data: {}", e);
-                break;
-            }
-        }
+        .unwrap_or_else(|e| panic!("Failed to start: {:#}", e));
+    let server = Arc::new(server);
+    let mut listeners = JoinSet::new();
+    for (udp, tcp) in sockets {
+        listeners.spawn(server.clone().serve_udp(udp));
+        listeners.spawn(server.clone().serve_tcp(tcp));
     }
+    while listeners.join_next().await.is_some() {}
 }
 
-async fn resolve_query(addr: &str, query: Message) -> Result<Vec<u8>> {
-    info!("Resolving Query");
-    let request_buf = query.split_as_bytes();
-    let mut response_buf = vec![0u8; 512];
-    let mut response = query.create_answerless_response().as_bytes();
-
-    info!("Binding Socket");
-    let socket = UdpSocket::bind("localhost:0")
-        .await
-        .context("Failed binding to the resolver UdpSocket")?;
-    socket
-        .connect(addr)
-        .await
-        .context("Failed connectiong to the resolver UdpSocket")?;
-
-    for r in request_buf {
-        socket.send(&r).await.context("Failed sending query")?;
-        let len = socket
-            .recv(&mut response_buf)
-            .await
-            .context("Error receiving response")?;
-        response_buf.truncate(len);
-
-        let rsp = Message::parse_resolver_response(&response_buf).answer;
-        rsp.iter().for_each(|answer| {
-            response.append(&mut answer.as_bytes());
-        });
+// IPv6 sockets only take IPv6 traffic, so the same port can be bound on 0.0.0.0 and [::].
+fn new_socket(addr: SocketAddr, ty: Type, protocol: Protocol) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), ty, Some(protocol))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
     }
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+fn bind_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = new_socket(addr, Type::DGRAM, Protocol::UDP)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
 
-    info!("Returning response after resolution finished");
-    Ok(response)
+fn bind_tcp(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = new_socket(addr, Type::STREAM, Protocol::TCP)?;
+    // A restart shouldn't have to wait for the last run's connections to leave TIME_WAIT
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
 }
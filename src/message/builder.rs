@@ -0,0 +1,153 @@
+use super::answer::Answer;
+use super::edns::Edns;
+use super::header::Header;
+use super::message::Message;
+use super::question::Question;
+use super::types::{Opcode, QRIndicator, Rcode};
+use log::debug;
+
+// Assembles a message section by section. Header counts can't be set here, they are always
+// derived from what the sections actually hold.
+
+pub(crate) struct MessageBuilder {
+    message: Message,
+}
+
+impl MessageBuilder {
+    // An empty query with id 0 and every flag cleared.
+    pub fn new() -> Self {
+        MessageBuilder {
+            message: Message {
+                header: Header::default(),
+                questions: vec![],
+                answer: vec![],
+                authority: vec![],
+                additional: vec![],
+                edns: None,
+            },
+        }
+    }
+
+    // A response echoing the request's id, opcode, RD and CD bits and questions. The rcode
+    // starts out as whatever the request itself calls for.
+    pub fn response_to(request: &Message) -> Self {
+        Self::response_to_header(&request.header)
+            .rcode(request.response_rcode())
+            // Responses carry an OPT record only if the request did (RFC 6891 7)
+            .edns(request.edns.as_ref().map(|edns| Edns::new(edns.dnssec_ok)))
+            .questions(request.questions.iter().cloned())
+    }
+
+    // For requests of which only the header could be read.
+    pub fn response_to_header(header: &Header) -> Self {
+        Self::new()
+            .id(header.id)
+            .qr(QRIndicator::Response)
+            .opcode(header.opcode)
+            .recursion_desired(header.recursion_desired)
+            .checking_disabled(header.checking_disabled)
+    }
+
+    pub fn id(mut self, id: u16) -> Self {
+        self.message.header.id = id;
+        self
+    }
+
+    pub fn qr(mut self, qr: QRIndicator) -> Self {
+        self.message.header.qr = qr;
+        self
+    }
+
+    pub fn opcode(mut self, opcode: Opcode) -> Self {
+        self.message.header.opcode = opcode;
+        self
+    }
+
+    // Nothing we answer is authoritative data, only tests get to set it.
+    #[cfg(test)]
+    pub fn authoritative_answer(mut self, authoritative_answer: bool) -> Self {
+        self.message.header.authorative_answer = authoritative_answer;
+        self
+    }
+
+    pub fn truncation(mut self, truncation: bool) -> Self {
+        self.message.header.truncation = truncation;
+        self
+    }
+
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.message.header.recursion_desired = recursion_desired;
+        self
+    }
+
+    // Not advertised by us, only tests get to set it.
+    #[cfg(test)]
+    pub fn recursion_available(mut self, recursion_available: bool) -> Self {
+        self.message.header.recursion_available = recursion_available;
+        self
+    }
+
+    // Reserved and always sent as zero, only tests get to set it.
+    #[cfg(test)]
+    pub fn zero(mut self, zero: bool) -> Self {
+        self.message.header.zero = zero;
+        self
+    }
+
+    pub fn authentic_data(mut self, authentic_data: bool) -> Self {
+        self.message.header.authentic_data = authentic_data;
+        self
+    }
+
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.message.header.checking_disabled = checking_disabled;
+        self
+    }
+
+    pub fn rcode(mut self, rcode: Rcode) -> Self {
+        self.message.header.response_code = rcode;
+        self
+    }
+
+    pub fn edns(mut self, edns: Option<Edns>) -> Self {
+        self.message.edns = edns;
+        self
+    }
+
+    pub fn question(mut self, question: Question) -> Self {
+        self.message.questions.push(question);
+        self
+    }
+
+    pub fn questions(mut self, questions: impl IntoIterator<Item = Question>) -> Self {
+        self.message.questions.extend(questions);
+        self
+    }
+
+    pub fn answers(mut self, records: impl IntoIterator<Item = Answer>) -> Self {
+        self.message.answer.extend(records);
+        self
+    }
+
+    pub fn authority(mut self, records: impl IntoIterator<Item = Answer>) -> Self {
+        self.message.authority.extend(records);
+        self
+    }
+
+    pub fn additional(mut self, records: impl IntoIterator<Item = Answer>) -> Self {
+        self.message.additional.extend(records);
+        self
+    }
+
+    // The counts are filled in here as well so logged messages show the real numbers.
+    pub fn build(self) -> Message {
+        let mut message = self.message;
+        message.header.question_count = message.questions.len() as u16;
+        message.header.answer_record_count = message.answer.len() as u16;
+        message.header.authority_record_count = message.authority.len() as u16;
+        message.header.additional_record_count =
+            message.additional.len() as u16 + message.edns.is_some() as u16;
+        debug!("Built message: {:?}", message);
+        message
+    }
+}
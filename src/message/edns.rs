@@ -0,0 +1,102 @@
+use super::answer::Answer;
+use super::error::ParseError;
+use super::name::DomainName;
+use super::rdata::RData;
+use super::types::{QClass, QType};
+
+// Largest UDP payload we accept and advertise in our own OPT records. It stays below the common
+// path MTU so large answers don't get fragmented (DNS flag day 2020).
+pub(crate) const MAX_UDP_PAYLOAD_SIZE: u16 = 1232;
+// What a message may take up without EDNS (RFC 1035 4.2.1)
+pub(crate) const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 512;
+
+// EDNS(0) data carried by the OPT pseudo-record in the additional section (RFC 6891). The record
+// fields are repurposed: CLASS holds the UDP payload size and TTL the extended RCODE and flags.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Edns {
+    // Largest UDP response the sender can reassemble
+    pub udp_payload_size: u16,
+
+    // Upper 8 bits of the 12-bit RCODE, the lower 4 live in the header
+    pub extended_rcode: u8,
+
+    pub version: u8,
+
+    // DNSSEC OK, the sender wants DNSSEC records in the response
+    pub dnssec_ok: bool,
+
+    pub options: Vec<EdnsOption>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl Edns {
+    // What we attach to our own queries and responses.
+    pub fn new(dnssec_ok: bool) -> Self {
+        Edns {
+            udp_payload_size: MAX_UDP_PAYLOAD_SIZE,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok,
+            options: vec![],
+        }
+    }
+
+    // Takes the OPT record out of a parsed additional section, there may be at most one.
+    pub fn extract(additional: &mut Vec<Answer>) -> Result<Option<Self>, ParseError> {
+        let opt = additional
+            .iter()
+            .position(|record| record.answer_type == QType::OPT)
+            .map(|i| additional.remove(i));
+
+        if additional
+            .iter()
+            .any(|record| record.answer_type == QType::OPT)
+        {
+            return Err(ParseError::BadOpt("more than one OPT record"));
+        }
+
+        opt.map(Self::from_record).transpose()
+    }
+
+    fn from_record(record: Answer) -> Result<Self, ParseError> {
+        if !record.name.is_root() {
+            return Err(ParseError::BadOpt("owner name is not the root"));
+        }
+        let RData::OPT(options) = record.data else {
+            return Err(ParseError::BadOpt("RDATA is not a list of options"));
+        };
+
+        Ok(Edns {
+            udp_payload_size: record.class.as_u16(),
+            extended_rcode: (record.ttl >> 24) as u8,
+            version: (record.ttl >> 16) as u8,
+            dnssec_ok: (record.ttl >> 15) & 0b1 == 1,
+            options,
+        })
+    }
+
+    pub fn to_record(&self) -> Answer {
+        Answer {
+            name: DomainName::root(),
+            answer_type: QType::OPT,
+            class: QClass::from_u16(self.udp_payload_size),
+            ttl: (self.extended_rcode as u32) << 24
+                | (self.version as u32) << 16
+                | (self.dnssec_ok as u32) << 15,
+            data: RData::OPT(self.options.clone()),
+        }
+    }
+
+    // The size a response to this sender may take up, anything below the plain DNS limit is
+    // treated as that limit.
+    pub fn max_payload_size(&self) -> usize {
+        self.udp_payload_size
+            .clamp(DEFAULT_UDP_PAYLOAD_SIZE, MAX_UDP_PAYLOAD_SIZE) as usize
+    }
+}
@@ -0,0 +1,56 @@
+use super::types::QType;
+use thiserror::Error;
+
+// Everything that can be wrong with a message we received. Offsets are positions in the packet so
+// a malformed datagram can be tracked down from the logs.
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum ParseError {
+    #[error("message is {0} bytes long, shorter than the 12 byte header")]
+    TruncatedHeader(usize),
+
+    #[error("message ends in the middle of the question or record at offset {0}")]
+    UnexpectedEnd(usize),
+
+    #[error("bad label at offset {offset}: {reason}")]
+    BadLabel { offset: usize, reason: &'static str },
+
+    #[error("label at offset {offset} is {length} octets long, the limit is 63")]
+    LabelTooLong { offset: usize, length: usize },
+
+    #[error("name at offset {0} is longer than 255 octets")]
+    NameTooLong(usize),
+
+    #[error(
+        "compression pointer at offset {offset} targets {target}, which is not an earlier offset"
+    )]
+    ForwardPointer { offset: usize, target: usize },
+
+    #[error("name at offset {0} loops or follows too many compression pointers")]
+    PointerLoop(usize),
+
+    #[error("bad OPT record: {0}")]
+    BadOpt(&'static str),
+
+    #[error("{rtype:?} RDATA at offset {offset} overruns its RDLENGTH of {length}")]
+    RDataOverrun {
+        rtype: QType,
+        offset: usize,
+        length: u16,
+    },
+
+    #[error("{rtype:?} RDATA at offset {offset} is malformed: {reason}")]
+    BadRData {
+        rtype: QType,
+        offset: usize,
+        reason: &'static str,
+    },
+}
+
+// Why a message we built could not be written out.
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub(crate) enum EmitError {
+    #[error("message does not fit in the {limit} bytes available")]
+    TooLarge { limit: usize },
+}
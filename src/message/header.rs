@@ -1,7 +1,14 @@
-use super::message::AsBytes;
-use super::types::QRIndicator;
-
-#[derive(Debug, Clone)]
+use super::error::ParseError;
+use super::message::Emit;
+use super::types::{Opcode, QRIndicator, Rcode};
+use super::utils::LabelCompression;
+use super::wire::{fail, WireResult};
+use bytes::{BufMut, BytesMut};
+use nom::combinator::map;
+use nom::number::complete::{be_u16, be_u8};
+use nom::sequence::tuple;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Header {
     pub id: u16,
 
@@ -9,7 +16,7 @@ pub struct Header {
     pub qr: QRIndicator,
 
     // Specifies the kind of query in a message - 4bits
-    pub opcode: u8,
+    pub opcode: Opcode,
 
     // True if the responding server "owns" the domain queried
     pub authorative_answer: bool,
@@ -23,10 +30,18 @@ pub struct Header {
     // Server sets this to 1 indicate that recursion is availabl(e
     pub recursion_available: bool,
 
-    // Used by DNSSEC queries. At inception, it was reserved for future use - 3bitss
-    pub reserved: u8,
+    // Reserved for future use, must be zero
+    pub zero: bool,
+
+    // Authentic Data, all data in the response has been validated with DNSSEC (RFC 4035)
+    pub authentic_data: bool,
+
+    // Checking Disabled, the client does its own DNSSEC validation (RFC 4035)
+    pub checking_disabled: bool,
 
-    pub response_code: u8,
+    // The full rcode, only its lower 4 bits are written to the header itself. The rest goes into
+    // the OPT record when the message is serialized.
+    pub response_code: Rcode,
 
     pub question_count: u16,
 
@@ -37,45 +52,46 @@ pub struct Header {
     pub additional_record_count: u16,
 }
 
-impl AsBytes for Header {
-    fn as_bytes(&self) -> Vec<u8> {
-        vec![
-            (self.id >> 8) as u8,
-            (self.id & 0xFF) as u8,
+impl Emit for Header {
+    fn emit(&self, buf: &mut BytesMut, _compression: &mut LabelCompression) {
+        buf.put_u16(self.id);
+        buf.put_u8(
             (self.qr as u8) << 7
-                | (self.opcode & 0b1111) << 3
+                | (self.opcode.as_u8() & 0b1111) << 3
                 | (self.authorative_answer as u8) << 2
                 | (self.truncation as u8) << 1
                 | (self.recursion_desired as u8),
+        );
+        buf.put_u8(
             ((self.recursion_available as u8) << 7)
-                | (self.reserved as u8 & 0b111) << 4
-                | (self.response_code as u8 & 0b1111),
-            (self.question_count >> 8) as u8,
-            (self.question_count & 0xFF) as u8,
-            (self.answer_record_count >> 8) as u8,
-            (self.answer_record_count & 0xFF) as u8,
-            (self.authority_record_count >> 8) as u8,
-            (self.authority_record_count & 0xFF) as u8,
-            (self.additional_record_count >> 8) as u8,
-            (self.additional_record_count & 0xFF) as u8,
-        ]
+                | (self.zero as u8) << 6
+                | (self.authentic_data as u8) << 5
+                | (self.checking_disabled as u8) << 4
+                | self.response_code.header_bits(),
+        );
+        buf.put_u16(self.question_count);
+        buf.put_u16(self.answer_record_count);
+        buf.put_u16(self.authority_record_count);
+        buf.put_u16(self.additional_record_count);
     }
 }
 
 impl Default for Header {
     fn default() -> Self {
         Header {
-            id: 1234,
-            qr: QRIndicator::Response,
-            opcode: 0,
+            id: 0,
+            qr: QRIndicator::Query,
+            opcode: Opcode::QUERY,
             authorative_answer: false,
             truncation: false,
             recursion_desired: false,
             recursion_available: false,
-            reserved: 0,
-            response_code: 0,
-            question_count: 1,
-            answer_record_count: 1,
+            zero: false,
+            authentic_data: false,
+            checking_disabled: false,
+            response_code: Rcode::NOERROR,
+            question_count: 0,
+            answer_record_count: 0,
             authority_record_count: 0,
             additional_record_count: 0,
         }
@@ -83,21 +99,29 @@ impl Default for Header {
 }
 
 impl Header {
-    pub fn parse(buf: &[u8]) -> Self {
-        Header {
-            id: ((buf[0] as u16) << 8 | buf[1] as u16),
-            qr: QRIndicator::from_uint(buf[2] >> 7),
-            opcode: (buf[2] >> 3) & 0xF,
-            authorative_answer: (buf[2] >> 2) & 0b1 == 1,
-            truncation: (buf[2] >> 1) & 0b1 == 1,
-            recursion_desired: buf[2] & 0b1 == 1,
-            recursion_available: (buf[3] >> 7 & 0b1) == 1,
-            reserved: (buf[3] >> 4 & 0b111),
-            response_code: (buf[3] & 0xF),
-            question_count: ((buf[4] as u16) << 8 | (buf[5] as u16)),
-            answer_record_count: ((buf[6] as u16) << 8 | (buf[7] as u16)),
-            authority_record_count: ((buf[8] as u16) << 8 | (buf[9] as u16)),
-            additional_record_count: ((buf[10] as u16) << 8 | (buf[11] as u16)),
+    pub fn parse(input: &[u8]) -> WireResult<'_, Self> {
+        if input.len() < 12 {
+            return fail(ParseError::TruncatedHeader(input.len()));
         }
+
+        let fields = tuple((be_u16, be_u8, be_u8, be_u16, be_u16, be_u16, be_u16));
+        map(fields, |(id, byte2, byte3, qd, an, ns, ar)| Header {
+            id,
+            qr: QRIndicator::from_uint(byte2 >> 7),
+            opcode: Opcode::from_u8((byte2 >> 3) & 0xF),
+            authorative_answer: (byte2 >> 2) & 0b1 == 1,
+            truncation: (byte2 >> 1) & 0b1 == 1,
+            recursion_desired: byte2 & 0b1 == 1,
+            recursion_available: (byte3 >> 7 & 0b1) == 1,
+            zero: (byte3 >> 6 & 0b1) == 1,
+            authentic_data: (byte3 >> 5 & 0b1) == 1,
+            checking_disabled: (byte3 >> 4 & 0b1) == 1,
+            // Combined with the extended bits once the OPT record has been parsed
+            response_code: Rcode::from_parts(byte3, 0),
+            question_count: qd,
+            answer_record_count: an,
+            authority_record_count: ns,
+            additional_record_count: ar,
+        })(input)
     }
 }
@@ -1,30 +1,58 @@
 use super::answer::Answer;
+use super::builder::MessageBuilder;
+use super::edns::{Edns, DEFAULT_UDP_PAYLOAD_SIZE};
+use super::error::{EmitError, ParseError};
 use super::header::Header;
+use super::message_ref::MessageRef;
 use super::question::Question;
-use crate::message::types::{QClass, QType};
+use super::rdata::RData;
+use super::utils::LabelCompression;
+use crate::message::types::{Opcode, QClass, QType, Rcode};
+use bytes::BytesMut;
 use log::debug;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// The host every local answer points to. The IPv6 address is a unique local one (RFC 4193), not
+// the IPv4 address mapped, which has no business in AAAA records (RFC 4038 4.2).
+const LOCAL_IPV4: Ipv4Addr = Ipv4Addr::new(192, 168, 0, 1);
+const LOCAL_IPV6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
 
 pub(crate) trait AsBytes {
     fn as_bytes(&self) -> Vec<u8>;
 }
 
-#[derive(Debug)]
+// Serialization straight into a shared buffer. Names may be compressed against anything of the
+// same message already in `buf`.
+pub(crate) trait Emit {
+    fn emit(&self, buf: &mut BytesMut, compression: &mut LabelCompression);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct Message {
     pub header: Header,
     pub questions: Vec<Question>,
     pub answer: Vec<Answer>,
+    pub authority: Vec<Answer>,
+    // Without the OPT pseudo-record, which is kept apart in `edns`
+    pub additional: Vec<Answer>,
+    pub edns: Option<Edns>,
+}
+
+// Parts written on their own have nothing to compress against.
+impl<T: Emit> AsBytes for T {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        self.emit(&mut buf, &mut LabelCompression::disabled());
+        buf.to_vec()
+    }
 }
 
 impl AsBytes for Message {
     fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes = self.header.as_bytes();
-        self.questions.iter().for_each(|question| {
-            bytes.extend(question.as_bytes());
-        });
-        self.answer.iter().for_each(|answer| {
-            bytes.extend(answer.as_bytes());
-        });
-        bytes
+        let mut buf = BytesMut::new();
+        self.emit(&mut buf, usize::MAX)
+            .expect("Without a limit every message fits");
+        buf.to_vec()
     }
 }
 
@@ -33,115 +61,211 @@ impl Default for Message {
         Message {
             questions: vec![Question::default()],
             answer: vec![Answer::default()],
+            authority: vec![],
+            additional: vec![],
+            edns: None,
             header: Header::default(),
         }
     }
 }
 
 impl Message {
-    pub fn parse_request(buf: &[u8]) -> Self {
-        let header = Header::parse(&buf);
-        debug!("Parsed request header: {:?}", header);
-
-        let (questions, _) =
-            Question::parse(&buf, header.question_count).expect("Parsing Should Succeed");
-        debug!("Parsed question(s): {:?}", questions);
-
-        Self {
-            header,
-            questions,
-            answer: vec![],
-        }
+    // The server checks the view before making it owned, this is for the tests and fuzz targets
+    #[allow(dead_code)]
+    pub fn parse_resolver_response(buf: &[u8]) -> Result<Self, ParseError> {
+        MessageRef::parse(buf)?.to_message()
     }
-    pub fn parse_resolver_response(buf: &[u8]) -> Self {
-        let header = Header::parse(&buf);
-        debug!("Parsed request header: {:?}", header);
-
-        let (questions, pos) =
-            Question::parse(&buf, header.question_count).expect("Parsing Should Succeed");
-        debug!("Parsed question(s): {:?}", questions);
-
-        let answers =
-            Answer::parse(&buf, pos, header.question_count).expect("Parsing Should Succeed");
-        debug!("Parsed answer(s): {:?}", answers);
-
-        Self {
-            header,
-            questions,
-            answer: answers,
+
+    // Appends the message to `buf`. If it takes more than `limit` bytes `buf` is left as it was
+    // and the error says so.
+    pub fn emit(&self, buf: &mut BytesMut, limit: usize) -> Result<(), EmitError> {
+        let start = buf.len();
+        let fits = |buf: &mut BytesMut| {
+            if buf.len() - start > limit {
+                buf.truncate(start);
+                return Err(EmitError::TooLarge { limit });
+            }
+            Ok(())
+        };
+
+        // The section counts are always taken from what is actually being written
+        let mut header = self.header.clone();
+        header.question_count = self.questions.len() as u16;
+        header.answer_record_count = self.answer.len() as u16;
+        header.authority_record_count = self.authority.len() as u16;
+        header.additional_record_count = self.additional.len() as u16 + self.edns.is_some() as u16;
+        // Without an OPT record the upper rcode bits have nowhere to go, and the lower 4 on their
+        // own mean something else entirely (BADVERS would read as NOERROR)
+        if self.edns.is_none() && header.response_code.extended_bits() != 0 {
+            header.response_code = Rcode::SERVFAIL;
+        }
+
+        let mut compression = LabelCompression::starting_at(start);
+        header.emit(buf, &mut compression);
+        for question in &self.questions {
+            question.emit(buf, &mut compression);
+            fits(buf)?;
+        }
+        for record in self
+            .answer
+            .iter()
+            .chain(&self.authority)
+            .chain(&self.additional)
+        {
+            record.emit(buf, &mut compression);
+            fits(buf)?;
         }
+        if let Some(edns) = &self.edns {
+            let mut edns = edns.clone();
+            edns.extended_rcode = self.header.response_code.extended_bits();
+            edns.to_record().emit(buf, &mut compression);
+        }
+        fits(buf)
     }
 
-    pub fn create_answerless_response(&self) -> Self {
-        let mut header = Header::default();
-
-        header.id = self.header.id;
-        header.opcode = self.header.opcode;
-        header.recursion_desired = self.header.recursion_desired;
-        header.question_count = self.header.question_count;
-        header.answer_record_count = self.header.question_count;
-        header.response_code = match self.header.opcode {
-            0 => 0,
-            _ => 4,
-        };
-        debug!("Updated response header: {:?}", header);
+    // The largest response the sender of this request is able to receive over UDP.
+    pub fn max_response_size(&self) -> usize {
+        self.edns
+            .as_ref()
+            .map_or(DEFAULT_UDP_PAYLOAD_SIZE as usize, |edns| {
+                edns.max_payload_size()
+            })
+    }
 
-        let response = Message {
-            header,
-            questions: self.questions.to_vec(),
-            answer: vec![],
-        };
-        debug!("Response message prepared: {:?}", response);
-        response
+    pub fn create_answerless_response(&self) -> Self {
+        MessageBuilder::response_to(self).build()
     }
 
     pub fn create_response(&self) -> Self {
+        if self.response_rcode() != Rcode::NOERROR {
+            return self.create_answerless_response();
+        }
+
         let mut answers: Vec<Answer> = vec![];
-        let mut header = Header::default();
         for q in &self.questions {
+            // Types we have no local data for get an empty answer
+            let Some(data) = Self::local_rdata(q) else {
+                debug!("No local data for {} {}", q.name, q.question_type);
+                continue;
+            };
             let answer = Answer {
                 name: q.name.clone(),
-                answer_type: QType::A,
+                answer_type: q.question_type,
                 class: QClass::IN,
                 ttl: 60,
-                length: 4,
-                data: vec![192, 168, 0, 1],
+                data,
             };
-            debug!("Generated answer: {:?}", answer);
+            debug!("Generated answer: {}", answer);
             answers.push(answer);
         }
 
-        header.id = self.header.id;
-        header.opcode = self.header.opcode;
-        header.recursion_desired = self.header.recursion_desired;
-        header.question_count = self.header.question_count;
-        header.answer_record_count = self.header.question_count;
-        header.response_code = match self.header.opcode {
-            0 => 0,
-            _ => 4,
-        };
-        debug!("Updated response header: {:?}", header);
+        MessageBuilder::response_to(self).answers(answers).build()
+    }
 
-        let response = Message {
-            header,
-            questions: self.questions.to_vec(),
-            answer: answers,
-        };
-        debug!("Response message prepared: {:?}", response);
-        response
+    // Every name resolves to the same local host, service lookups are pointed back at it.
+    fn local_rdata(question: &Question) -> Option<RData> {
+        match question.question_type {
+            QType::A => Some(RData::A(LOCAL_IPV4)),
+            QType::AAAA => Some(RData::AAAA(LOCAL_IPV6)),
+            // The service and protocol labels are stripped to get to the host
+            QType::SRV => Some(RData::SRV {
+                priority: 0,
+                weight: 0,
+                port: Self::service_port(question.name.labels().first()?)?,
+                target: question
+                    .name
+                    .ancestors()
+                    .find(|name| !name.labels().first().is_some_and(|l| l.starts_with(b"_")))?,
+            }),
+            // The "A" flag makes the client continue with an address lookup of the replacement
+            QType::NAPTR => Some(RData::NAPTR {
+                order: 100,
+                preference: 10,
+                flags: b"A".to_vec(),
+                services: vec![],
+                regexp: vec![],
+                replacement: question.name.clone(),
+            }),
+            _ => None,
+        }
     }
-    pub fn split_as_bytes(&self) -> Vec<Vec<u8>> {
-        let mut result: Vec<Vec<u8>> = vec![];
-        let mut header = self.header.clone();
 
-        header.question_count = 1;
-        self.questions.iter().for_each(|question| {
-            let mut bytes: Vec<u8> = header.as_bytes();
+    // Well-known ports of the services we get asked about, others get no local SRV answer since
+    // there is no port to point them to.
+    fn service_port(service: &[u8]) -> Option<u16> {
+        match service.to_ascii_lowercase().as_slice() {
+            b"_http" => Some(80),
+            b"_https" => Some(443),
+            b"_ldap" => Some(389),
+            b"_kerberos" => Some(88),
+            b"_sip" => Some(5060),
+            b"_sips" => Some(5061),
+            b"_xmpp-client" => Some(5222),
+            b"_xmpp-server" => Some(5269),
+            b"_imaps" => Some(993),
+            b"_submission" => Some(587),
+            _ => None,
+        }
+    }
 
-            bytes.extend(question.as_bytes());
+    // The rcode a response to this request starts out with, before any resolution is done.
+    pub fn response_rcode(&self) -> Rcode {
+        match self.header.opcode {
+            // We only know EDNS version 0 (RFC 6891 6.1.3)
+            _ if self.edns.as_ref().is_some_and(|edns| edns.version > 0) => Rcode::BADVERS,
+            Opcode::QUERY if self.questions.is_empty() => Rcode::FORMERR,
+            // Neither the local data nor what we forward covers anything but the Internet class
+            Opcode::QUERY if self.questions.iter().any(|q| q.class != QClass::IN) => Rcode::REFUSED,
+            Opcode::QUERY => Rcode::NOERROR,
+            _ => Rcode::NOTIMP,
+        }
+    }
+
+    // Like `emit`, but drops whole RRsets until the message fits: additional records first, then
+    // authority, then answers. TC is only set once answer data had to go (RFC 2181 9), the OPT
+    // record always stays.
+    pub fn emit_truncated(&self, buf: &mut BytesMut, limit: usize) -> Result<(), EmitError> {
+        let mut message = self.clone();
+        loop {
+            let e = match message.emit(buf, limit) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            let section = [
+                &mut message.additional,
+                &mut message.authority,
+                &mut message.answer,
+            ]
+            .into_iter()
+            .find(|section| !section.is_empty())
+            .ok_or(e)?;
+            let last = section.last().expect("Only non-empty sections are picked");
+            let (name, rtype, class) = (last.name.clone(), last.answer_type, last.class);
+            section.retain(|r| !(r.name == name && r.answer_type == rtype && r.class == class));
+            debug!(
+                "Dropped the {} {} {} RRset to fit {} bytes",
+                name, class, rtype, limit
+            );
+            message.header.truncation =
+                self.header.truncation || message.answer.len() < self.answer.len();
+        }
+    }
 
-            result.push(bytes)
-        });
-        result
+    // One query per question, each with `edns` attached as its OPT record. The client's RD, AD
+    // and CD bits are passed on upstream.
+    pub fn split(&self, edns: &Edns) -> Vec<Message> {
+        self.questions
+            .iter()
+            .map(|question| {
+                MessageBuilder::new()
+                    .id(self.header.id)
+                    .recursion_desired(self.header.recursion_desired)
+                    .authentic_data(self.header.authentic_data)
+                    .checking_disabled(self.header.checking_disabled)
+                    .question(question.clone())
+                    .edns(Some(edns.clone()))
+                    .build()
+            })
+            .collect()
     }
 }
@@ -0,0 +1,269 @@
+use super::answer::Answer;
+use super::edns::Edns;
+use super::error::ParseError;
+use super::header::Header;
+use super::message::Message;
+use super::name::DomainName;
+use super::question::Question;
+use super::rdata::RData;
+use super::types::{QClass, QRIndicator, QType, Rcode};
+use super::wire::{skip_n, Label, Wire, WireResult};
+use log::debug;
+use nom::combinator::map;
+use nom::multi::length_data;
+use nom::number::complete::{be_u16, be_u32};
+use nom::sequence::tuple;
+use std::fmt;
+
+// A borrowed view of a received packet. The framing of the whole packet (names, fixed fields and
+// RDLENGTHs) is validated once up front, after that questions and records are read lazily
+// straight out of the receive buffer. RDATA contents are only decoded when converting a record
+// to its owned form.
+
+#[derive(Debug, Clone)]
+pub(crate) struct MessageRef<'a> {
+    wire: Wire<'a>,
+    header: Header,
+    // The bytes each section takes up
+    questions: &'a [u8],
+    answer: &'a [u8],
+    authority: &'a [u8],
+    additional: &'a [u8],
+}
+
+// A name inside the packet, pointers are followed when its labels are read.
+#[derive(Clone, Copy)]
+pub(crate) struct NameRef<'a> {
+    wire: Wire<'a>,
+    input: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct QuestionRef<'a> {
+    pub name: NameRef<'a>,
+    pub question_type: QType,
+    pub class: QClass,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RecordRef<'a> {
+    wire: Wire<'a>,
+    pub name: NameRef<'a>,
+    pub record_type: QType,
+    pub class: QClass,
+    pub ttl: u32,
+    rdata: &'a [u8],
+}
+
+impl<'a> MessageRef<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, ParseError> {
+        let wire = Wire::new(buf);
+        let (_, message) = Self::sections(wire, buf).map_err(|e| wire.error(e))?;
+        debug!("Parsed header: {:?}", message.header);
+        Ok(message)
+    }
+
+    // Anything after the last record is ignored.
+    fn sections(wire: Wire<'a>, input: &'a [u8]) -> WireResult<'a, Self> {
+        let (input, header) = Header::parse(input)?;
+        let question = move |input| wire.entry(input, QuestionRef::parse);
+        let record = move |input| wire.entry(input, RecordRef::parse);
+
+        let (input, questions) = skip_n(header.question_count, question)(input)?;
+        let (input, answer) = skip_n(header.answer_record_count, record)(input)?;
+        let (input, authority) = skip_n(header.authority_record_count, record)(input)?;
+        let (input, additional) = skip_n(header.additional_record_count, record)(input)?;
+
+        Ok((
+            input,
+            MessageRef {
+                wire,
+                header,
+                questions,
+                answer,
+                authority,
+                additional,
+            },
+        ))
+    }
+
+    pub fn questions(&self) -> impl Iterator<Item = QuestionRef<'a>> {
+        Self::entries(self.wire, self.questions, QuestionRef::parse)
+    }
+
+    pub fn answers(&self) -> impl Iterator<Item = RecordRef<'a>> {
+        Self::entries(self.wire, self.answer, RecordRef::parse)
+    }
+
+    pub fn authority(&self) -> impl Iterator<Item = RecordRef<'a>> {
+        Self::entries(self.wire, self.authority, RecordRef::parse)
+    }
+
+    // Including the OPT record if there is one.
+    pub fn additional(&self) -> impl Iterator<Item = RecordRef<'a>> {
+        Self::entries(self.wire, self.additional, RecordRef::parse)
+    }
+
+    // The section was validated as a whole, so its entries parse again without errors.
+    fn entries<T>(
+        wire: Wire<'a>,
+        mut section: &'a [u8],
+        parser: fn(Wire<'a>, &'a [u8]) -> WireResult<'a, T>,
+    ) -> impl Iterator<Item = T> + 'a
+    where
+        T: 'a,
+    {
+        std::iter::from_fn(move || {
+            if section.is_empty() {
+                return None;
+            }
+            let (rest, entry) = parser(wire, section).ok()?;
+            section = rest;
+            Some(entry)
+        })
+    }
+
+    // Whether this answers `query`: a response with the same id and the same questions, names
+    // compared ignoring case (RFC 5452 4.3).
+    pub fn is_response_to(&self, query: &Message) -> bool {
+        self.header.qr == QRIndicator::Response
+            && self.header.id == query.header.id
+            && self
+                .questions()
+                .map(QuestionRef::to_question)
+                .eq(query.questions.iter().cloned())
+    }
+
+    // Decodes everything into an owned message, with the OPT record moved into `edns` and the
+    // full extended rcode in the header.
+    pub fn to_message(&self) -> Result<Message, ParseError> {
+        let mut header = self.header.clone();
+        let questions: Vec<Question> = self.questions().map(|q| q.to_question()).collect();
+        debug!("Parsed question(s): {:?}", questions);
+
+        let answer = self
+            .answers()
+            .map(|r| r.to_answer())
+            .collect::<Result<Vec<_>, _>>()?;
+        debug!("Parsed answer(s): {:?}", answer);
+
+        let authority = self
+            .authority()
+            .map(|r| r.to_answer())
+            .collect::<Result<Vec<_>, _>>()?;
+        debug!("Parsed authority record(s): {:?}", authority);
+
+        let mut additional = self
+            .additional()
+            .map(|r| r.to_answer())
+            .collect::<Result<Vec<_>, _>>()?;
+        debug!("Parsed additional record(s): {:?}", additional);
+
+        let edns = Edns::extract(&mut additional)?;
+        debug!("Parsed EDNS: {:?}", edns);
+
+        if let Some(edns) = &edns {
+            header.response_code =
+                Rcode::from_parts(header.response_code.header_bits(), edns.extended_rcode);
+        }
+
+        Ok(Message {
+            header,
+            questions,
+            answer,
+            authority,
+            additional,
+            edns,
+        })
+    }
+}
+
+impl<'a> NameRef<'a> {
+    fn parse(wire: Wire<'a>, input: &'a [u8]) -> WireResult<'a, Self> {
+        let (rest, ()) = wire.walk_name(input, |_| ())?;
+        Ok((rest, NameRef { wire, input }))
+    }
+
+    // Already validated, so pointers can be followed without checking them again.
+    pub fn labels(&self) -> impl Iterator<Item = &'a [u8]> {
+        let wire = self.wire;
+        let mut input = self.input;
+        std::iter::from_fn(move || loop {
+            let (rest, label) = wire.label(input).ok()?;
+            match label {
+                Label::Data(label) => {
+                    input = rest;
+                    return Some(label);
+                }
+                Label::Pointer(target) => input = wire.at(target),
+                Label::End => return None,
+            }
+        })
+    }
+
+    pub fn to_name(self) -> DomainName {
+        DomainName::from_labels(self.labels().map(<[u8]>::to_vec).collect())
+            .expect("Name lengths were validated when parsing")
+    }
+}
+
+impl fmt::Display for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_name())
+    }
+}
+
+impl fmt::Debug for NameRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NameRef(\"{}\")", self)
+    }
+}
+
+impl<'a> QuestionRef<'a> {
+    fn parse(wire: Wire<'a>, input: &'a [u8]) -> WireResult<'a, Self> {
+        let name = move |input| NameRef::parse(wire, input);
+        map(
+            tuple((name, be_u16, be_u16)),
+            |(name, question_type, class)| QuestionRef {
+                name,
+                question_type: QType::from_u16(question_type),
+                class: QClass::from_u16(class),
+            },
+        )(input)
+    }
+
+    pub fn to_question(self) -> Question {
+        Question {
+            name: self.name.to_name(),
+            question_type: self.question_type,
+            class: self.class,
+        }
+    }
+}
+
+impl<'a> RecordRef<'a> {
+    fn parse(wire: Wire<'a>, input: &'a [u8]) -> WireResult<'a, Self> {
+        let name = move |input| NameRef::parse(wire, input);
+        map(
+            tuple((name, be_u16, be_u16, be_u32, length_data(be_u16))),
+            |(name, record_type, class, ttl, rdata)| RecordRef {
+                wire,
+                name,
+                record_type: QType::from_u16(record_type),
+                class: QClass::from_u16(class),
+                ttl,
+                rdata,
+            },
+        )(input)
+    }
+
+    pub fn to_answer(self) -> Result<Answer, ParseError> {
+        Ok(Answer {
+            name: self.name.to_name(),
+            answer_type: self.record_type,
+            class: self.class,
+            ttl: self.ttl,
+            data: RData::parse(self.wire, self.rdata, self.record_type, self.class)?,
+        })
+    }
+}
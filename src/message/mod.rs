@@ -1,6 +1,16 @@
 pub mod answer;
+pub mod builder;
+pub mod edns;
+pub mod error;
 pub mod header;
+#[allow(clippy::module_inception)]
 pub mod message;
+pub mod message_ref;
+pub mod name;
 pub mod question;
+pub mod rdata;
+#[cfg(test)]
+mod tests;
 pub mod types;
 pub mod utils;
+pub mod wire;
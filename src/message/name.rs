@@ -0,0 +1,178 @@
+use super::error::ParseError;
+use super::utils::{MAX_LABEL_LENGTH, MAX_NAME_LENGTH};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+// A domain name as the sequence of its labels, root label excluded. Labels are raw bytes since
+// DNS places no restriction on their contents, so a label may even hold a literal dot.
+// Comparison and hashing ignore ASCII case (RFC 4343), the original case is kept for output.
+
+#[derive(Clone, Default)]
+pub(crate) struct DomainName {
+    labels: Vec<Vec<u8>>,
+}
+
+impl DomainName {
+    pub fn root() -> Self {
+        DomainName { labels: vec![] }
+    }
+
+    // Checks the label and total length limits, labels must not be empty.
+    pub fn from_labels(labels: Vec<Vec<u8>>) -> Result<Self, ParseError> {
+        let mut wire_length = 1;
+        for label in &labels {
+            if label.is_empty() {
+                return Err(ParseError::BadLabel {
+                    offset: wire_length - 1,
+                    reason: "empty label",
+                });
+            }
+            if label.len() > MAX_LABEL_LENGTH {
+                return Err(ParseError::LabelTooLong {
+                    offset: wire_length - 1,
+                    length: label.len(),
+                });
+            }
+            wire_length += label.len() + 1;
+        }
+        if wire_length > MAX_NAME_LENGTH {
+            return Err(ParseError::NameTooLong(0));
+        }
+        Ok(DomainName { labels })
+    }
+
+    pub fn labels(&self) -> &[Vec<u8>] {
+        &self.labels
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // The name with its leftmost label removed, None for the root.
+    pub fn parent(&self) -> Option<Self> {
+        if self.is_root() {
+            return None;
+        }
+        Some(DomainName {
+            labels: self.labels[1..].to_vec(),
+        })
+    }
+
+    // The name itself followed by each of its ancestors, ending with the root.
+    pub fn ancestors(&self) -> impl Iterator<Item = DomainName> {
+        std::iter::successors(Some(self.clone()), |name| name.parent())
+    }
+
+    // True if `other` is this name or one of its ancestors.
+    pub fn is_subdomain_of(&self, other: &DomainName) -> bool {
+        self.labels.len() >= other.labels.len()
+            && self.labels[self.labels.len() - other.labels.len()..]
+                .iter()
+                .zip(&other.labels)
+                .all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl PartialEq for DomainName {
+    fn eq(&self, other: &Self) -> bool {
+        self.labels.len() == other.labels.len() && self.is_subdomain_of(other)
+    }
+}
+
+impl Eq for DomainName {}
+
+impl Hash for DomainName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for label in &self.labels {
+            state.write_u8(label.len() as u8);
+            for byte in label {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+// Presentation format, dots and backslashes inside labels are escaped with a backslash and
+// anything unprintable as \DDD. The root is written as a single dot.
+impl fmt::Display for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return write!(f, ".");
+        }
+        for (i, label) in self.labels.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            for &byte in label {
+                match byte {
+                    b'.' | b'\\' => write!(f, "\\{}", byte as char)?,
+                    0x21..=0x7E => write!(f, "{}", byte as char)?,
+                    _ => write!(f, "\\{:03}", byte)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Byte vectors make for unreadable logs, so debug output uses the presentation format too.
+impl fmt::Debug for DomainName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DomainName(\"{}\")", self)
+    }
+}
+
+// Parses the presentation format written by Display, a trailing dot is optional.
+impl FromStr for DomainName {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut labels = vec![];
+        let mut label = vec![];
+        let bytes = s.as_bytes();
+        let mut pos = 0;
+
+        if s == "." {
+            return Ok(Self::root());
+        }
+
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b'.' => {
+                    labels.push(std::mem::take(&mut label));
+                    pos += 1;
+                }
+                b'\\' => {
+                    let digits = bytes.get(pos + 1..pos + 4).unwrap_or_default();
+                    if digits.len() == 3 && digits.iter().all(u8::is_ascii_digit) {
+                        let value = digits
+                            .iter()
+                            .fold(0u16, |acc, d| acc * 10 + (d - b'0') as u16);
+                        label.push(u8::try_from(value).map_err(|_| ParseError::BadLabel {
+                            offset: pos,
+                            reason: "escaped value above 255",
+                        })?);
+                        pos += 4;
+                    } else {
+                        label.push(*bytes.get(pos + 1).ok_or(ParseError::BadLabel {
+                            offset: pos,
+                            reason: "dangling escape",
+                        })?);
+                        pos += 2;
+                    }
+                }
+                byte => {
+                    label.push(byte);
+                    pos += 1;
+                }
+            }
+        }
+        if !label.is_empty() {
+            labels.push(label);
+        }
+
+        Self::from_labels(labels)
+    }
+}
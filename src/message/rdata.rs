@@ -0,0 +1,466 @@
+use super::edns::EdnsOption;
+use super::error::ParseError;
+use super::message::Emit;
+use super::name::DomainName;
+use super::types::{QClass, QType};
+use super::utils::LabelCompression;
+use super::wire::{Wire, WireError, WireResult};
+use bytes::{BufMut, BytesMut};
+use nom::combinator::{cut, eof, map, not, rest};
+use nom::multi::{length_data, many0};
+use nom::number::complete::{be_u128, be_u16, be_u32, be_u8};
+use nom::sequence::{preceded, tuple};
+use nom::Err;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Typed RDATA of a resource record. The variant is picked from the record's TYPE when parsing,
+// anything we don't understand is kept as opaque bytes so it can still be forwarded. Every name
+// that RFC 1035 allows to be compressed is decoded here, so none of them is ever copied with
+// pointers into the packet it was read from.
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RData {
+    // A host address
+    A(Ipv4Addr),
+    // An authoritative name server
+    NS(DomainName),
+    // A mail destination (Obsolete use MX)
+    MD(DomainName),
+    // A mail forwarder (Obsolete use MX)
+    MF(DomainName),
+    // The canonical name for an alias
+    CNAME(DomainName),
+    // Marks the start of a zone of authority
+    SOA {
+        mname: DomainName,
+        rname: DomainName,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+    },
+    // A mailbox domain name (EXPERIMENTAL)
+    MB(DomainName),
+    // A mail group member (EXPERIMENTAL)
+    MG(DomainName),
+    // A mail rename domain name (EXPERIMENTAL)
+    MR(DomainName),
+    // A domain name pointer
+    PTR(DomainName),
+    // Mail exchange
+    MX {
+        preference: u16,
+        exchange: DomainName,
+    },
+    // One or more character-strings
+    TXT(Vec<Vec<u8>>),
+    // Host information, both fields are character-strings
+    HINFO {
+        cpu: Vec<u8>,
+        os: Vec<u8>,
+    },
+    // Mailbox or mail list information
+    MINFO {
+        rmailbx: DomainName,
+        emailbx: DomainName,
+    },
+    // A well known service description
+    WKS {
+        address: Ipv4Addr,
+        protocol: u8,
+        bitmap: Vec<u8>,
+    },
+    // Anything at all, up to 65535 octets
+    NULL(Vec<u8>),
+    // An IPv6 host address
+    AAAA(Ipv6Addr),
+    // The location of a service, the target is never compressed (RFC 2782)
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DomainName,
+    },
+    // A rewrite rule, the replacement is never compressed (RFC 3403)
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: Vec<u8>,
+        services: Vec<u8>,
+        regexp: Vec<u8>,
+        replacement: DomainName,
+    },
+    // The options of an EDNS OPT pseudo-record
+    OPT(Vec<EdnsOption>),
+    // RDATA of a type we don't model, kept verbatim
+    Unknown(Vec<u8>),
+}
+
+impl RData {
+    // Parses `rdata`, which holds exactly RDLENGTH bytes. It is a slice of the whole message so
+    // that compressed names inside the RDATA can be followed. Types whose layout is only defined
+    // for the Internet class are opaque in any other (RFC 3597 5).
+    pub fn parse<'a>(
+        wire: Wire<'a>,
+        rdata: &'a [u8],
+        rtype: QType,
+        class: QClass,
+    ) -> Result<Self, ParseError> {
+        let offset = wire.offset(rdata);
+        let bad = |reason| ParseError::BadRData {
+            rtype,
+            offset,
+            reason,
+        };
+        let name = move |input| wire.name(input);
+
+        let parsed: WireResult<'a, Self> = match rtype {
+            QType::A | QType::WKS | QType::AAAA | QType::SRV if class != QClass::IN => {
+                map(rest, |data: &[u8]| Self::Unknown(data.to_vec()))(rdata)
+            }
+            QType::A if rdata.len() != 4 => return Err(bad("an address must be 4 bytes long")),
+            QType::A => map(be_u32, |address| Self::A(Ipv4Addr::from(address)))(rdata),
+            QType::NS => map(name, Self::NS)(rdata),
+            QType::CNAME => map(name, Self::CNAME)(rdata),
+            QType::PTR => map(name, Self::PTR)(rdata),
+            QType::MD => map(name, Self::MD)(rdata),
+            QType::MF => map(name, Self::MF)(rdata),
+            QType::MB => map(name, Self::MB)(rdata),
+            QType::MG => map(name, Self::MG)(rdata),
+            QType::MR => map(name, Self::MR)(rdata),
+            QType::SOA => map(
+                tuple((name, name, be_u32, be_u32, be_u32, be_u32, be_u32)),
+                |(mname, rname, serial, refresh, retry, expire, minimum)| Self::SOA {
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                },
+            )(rdata),
+            QType::MX => map(tuple((be_u16, name)), |(preference, exchange)| Self::MX {
+                preference,
+                exchange,
+            })(rdata),
+            QType::MINFO => map(tuple((name, name)), |(rmailbx, emailbx)| Self::MINFO {
+                rmailbx,
+                emailbx,
+            })(rdata),
+            QType::TXT => map(until_end(character_string), Self::TXT)(rdata),
+            QType::HINFO => map(tuple((character_string, character_string)), |(cpu, os)| {
+                Self::HINFO { cpu, os }
+            })(rdata),
+            QType::WKS => map(
+                tuple((be_u32, be_u8, rest)),
+                |(address, protocol, bitmap): (u32, u8, &[u8])| Self::WKS {
+                    address: Ipv4Addr::from(address),
+                    protocol,
+                    bitmap: bitmap.to_vec(),
+                },
+            )(rdata),
+            QType::NULL => map(rest, |data: &[u8]| Self::NULL(data.to_vec()))(rdata),
+            QType::AAAA if rdata.len() != 16 => {
+                return Err(bad("an address must be 16 bytes long"))
+            }
+            QType::AAAA => map(be_u128, |address| Self::AAAA(Ipv6Addr::from(address)))(rdata),
+            // Senders must not compress these names but decompressing them anyway is harmless
+            QType::SRV => map(
+                tuple((be_u16, be_u16, be_u16, name)),
+                |(priority, weight, port, target)| Self::SRV {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                },
+            )(rdata),
+            QType::NAPTR => map(
+                tuple((
+                    be_u16,
+                    be_u16,
+                    character_string,
+                    character_string,
+                    character_string,
+                    name,
+                )),
+                |(order, preference, flags, services, regexp, replacement)| Self::NAPTR {
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement,
+                },
+            )(rdata),
+            QType::OPT => map(until_end(tuple((be_u16, length_data(be_u16)))), |options| {
+                Self::OPT(
+                    options
+                        .into_iter()
+                        .map(|(code, data): (u16, &[u8])| EdnsOption {
+                            code,
+                            data: data.to_vec(),
+                        })
+                        .collect(),
+                )
+            })(rdata),
+            QType::Unknown(_) => map(rest, |data: &[u8]| Self::Unknown(data.to_vec()))(rdata),
+        };
+
+        match parsed {
+            Ok(([], data)) => Ok(data),
+            Ok(_) => Err(bad("trailing bytes after the last field")),
+            // The input is cut off at the end of the RDATA, so running out of bytes means a field
+            // overran RDLENGTH rather than the message
+            Err(Err::Error(WireError::Nom(_)) | Err::Failure(WireError::Nom(_))) => {
+                Err(ParseError::RDataOverrun {
+                    rtype,
+                    offset,
+                    length: rdata.len() as u16,
+                })
+            }
+            Err(e) => Err(wire.error(e)),
+        }
+    }
+
+    fn write_character_string(buf: &mut BytesMut, string: &[u8]) {
+        buf.put_u8(string.len() as u8);
+        buf.put_slice(string);
+    }
+}
+
+fn character_string(input: &[u8]) -> WireResult<'_, Vec<u8>> {
+    map(length_data(be_u8), <[u8]>::to_vec)(input)
+}
+
+// Repeats `parser` until the input is used up. Running out in the middle of an entry is an
+// error rather than the end of the list.
+fn until_end<'a, T>(
+    parser: impl FnMut(&'a [u8]) -> WireResult<'a, T>,
+) -> impl FnMut(&'a [u8]) -> WireResult<'a, Vec<T>> {
+    many0(preceded(not(eof), cut(parser)))
+}
+
+// Writes the RDATA without its length prefix, the owning record takes care of that. All names in
+// the RFC 1035 types may be compressed, later types always write them in full.
+impl Emit for RData {
+    fn emit(&self, buf: &mut BytesMut, compression: &mut LabelCompression) {
+        match self {
+            Self::A(address) => buf.put_slice(&address.octets()),
+            Self::NS(name)
+            | Self::CNAME(name)
+            | Self::PTR(name)
+            | Self::MD(name)
+            | Self::MF(name)
+            | Self::MB(name)
+            | Self::MG(name)
+            | Self::MR(name) => compression.write_label(buf, name),
+            Self::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => {
+                compression.write_label(buf, mname);
+                compression.write_label(buf, rname);
+                for value in [serial, refresh, retry, expire, minimum] {
+                    buf.put_u32(*value);
+                }
+            }
+            Self::MX {
+                preference,
+                exchange,
+            } => {
+                buf.put_u16(*preference);
+                compression.write_label(buf, exchange);
+            }
+            Self::TXT(strings) => {
+                for string in strings {
+                    Self::write_character_string(buf, string);
+                }
+            }
+            Self::HINFO { cpu, os } => {
+                Self::write_character_string(buf, cpu);
+                Self::write_character_string(buf, os);
+            }
+            Self::MINFO { rmailbx, emailbx } => {
+                compression.write_label(buf, rmailbx);
+                compression.write_label(buf, emailbx);
+            }
+            Self::WKS {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                buf.put_slice(&address.octets());
+                buf.put_u8(*protocol);
+                buf.put_slice(bitmap);
+            }
+            Self::NULL(data) | Self::Unknown(data) => buf.put_slice(data),
+            Self::OPT(options) => {
+                for option in options {
+                    buf.put_u16(option.code);
+                    buf.put_u16(option.data.len() as u16);
+                    buf.put_slice(&option.data);
+                }
+            }
+            Self::AAAA(address) => buf.put_slice(&address.octets()),
+            Self::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => {
+                for value in [priority, weight, port] {
+                    buf.put_u16(*value);
+                }
+                LabelCompression::disabled().write_label(buf, target);
+            }
+            Self::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                buf.put_u16(*order);
+                buf.put_u16(*preference);
+                Self::write_character_string(buf, flags);
+                Self::write_character_string(buf, services);
+                Self::write_character_string(buf, regexp);
+                LabelCompression::disabled().write_label(buf, replacement);
+            }
+        }
+    }
+}
+
+// Presentation format as used in zone files. Types without one, NULL included, use the generic
+// `\# length hex` form from RFC 3597.
+impl fmt::Display for RData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::A(address) => write!(f, "{}", address),
+            Self::NS(name)
+            | Self::CNAME(name)
+            | Self::PTR(name)
+            | Self::MD(name)
+            | Self::MF(name)
+            | Self::MB(name)
+            | Self::MG(name)
+            | Self::MR(name) => write!(f, "{}", name),
+            Self::SOA {
+                mname,
+                rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+            } => write!(
+                f,
+                "{} {} {} {} {} {} {}",
+                mname, rname, serial, refresh, retry, expire, minimum
+            ),
+            Self::MX {
+                preference,
+                exchange,
+            } => write!(f, "{} {}", preference, exchange),
+            Self::TXT(strings) => {
+                for (i, string) in strings.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write_quoted(f, string)?;
+                }
+                Ok(())
+            }
+            Self::HINFO { cpu, os } => {
+                write_quoted(f, cpu)?;
+                write!(f, " ")?;
+                write_quoted(f, os)
+            }
+            Self::MINFO { rmailbx, emailbx } => write!(f, "{} {}", rmailbx, emailbx),
+            Self::WKS {
+                address,
+                protocol,
+                bitmap,
+            } => {
+                write!(f, "{} {}", address, protocol)?;
+                for (i, byte) in bitmap.iter().enumerate() {
+                    for bit in 0..8 {
+                        if byte & (0x80 >> bit) != 0 {
+                            write!(f, " {}", i * 8 + bit)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Self::AAAA(address) => write!(f, "{}", address),
+            Self::SRV {
+                priority,
+                weight,
+                port,
+                target,
+            } => write!(f, "{} {} {} {}", priority, weight, port, target),
+            Self::NAPTR {
+                order,
+                preference,
+                flags,
+                services,
+                regexp,
+                replacement,
+            } => {
+                write!(f, "{} {} ", order, preference)?;
+                for string in [flags, services, regexp] {
+                    write_quoted(f, string)?;
+                    write!(f, " ")?;
+                }
+                write!(f, "{}", replacement)
+            }
+            // There is no presentation format for OPT, options are listed as CODE:HEX
+            Self::OPT(options) => {
+                for (i, option) in options.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}:", option.code)?;
+                    for byte in &option.data {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                Ok(())
+            }
+            Self::NULL(data) | Self::Unknown(data) => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                    for byte in data {
+                        write!(f, "{:02x}", byte)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Writes a character-string in double quotes, escaping quotes and backslashes with a backslash
+// and anything unprintable as \DDD.
+fn write_quoted(f: &mut fmt::Formatter<'_>, string: &[u8]) -> fmt::Result {
+    write!(f, "\"")?;
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+            0x20..=0x7E => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{:03}", byte)?,
+        }
+    }
+    write!(f, "\"")
+}
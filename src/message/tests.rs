@@ -0,0 +1,436 @@
+use super::answer::Answer;
+use super::builder::MessageBuilder;
+use super::edns::{Edns, EdnsOption};
+use super::error::ParseError;
+use super::message::{AsBytes, Message};
+use super::message_ref::MessageRef;
+use super::name::DomainName;
+use super::question::Question;
+use super::rdata::RData;
+use super::types::{Opcode, QClass, QRIndicator, QType, Rcode};
+use bytes::BytesMut;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// Generators for whole messages, used to check that whatever we can build survives a trip over
+// the wire unchanged. Sizes are kept small enough that every message stays below 64 KiB.
+
+fn name() -> impl Strategy<Value = DomainName> {
+    // Few distinct labels so that names share suffixes and compression kicks in
+    let label = prop_oneof![
+        Just(b"com".to_vec()),
+        Just(b"example".to_vec()),
+        Just(b"a.b".to_vec()),
+        vec(any::<u8>(), 1..=63),
+    ];
+    vec(label, 0..4).prop_filter_map("name too long", |labels| {
+        DomainName::from_labels(labels).ok()
+    })
+}
+
+fn character_string() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..=255)
+}
+
+// The types we model are generated in their parsed form, anything else as opaque bytes.
+fn rdata() -> impl Strategy<Value = (QType, RData)> {
+    let unknown_type = any::<u16>()
+        .prop_map(QType::from_u16)
+        .prop_filter("known type", |t| matches!(t, QType::Unknown(_)));
+
+    prop_oneof![
+        any::<u32>().prop_map(|a| (QType::A, RData::A(Ipv4Addr::from(a)))),
+        name().prop_map(|n| (QType::NS, RData::NS(n))),
+        name().prop_map(|n| (QType::MD, RData::MD(n))),
+        name().prop_map(|n| (QType::MF, RData::MF(n))),
+        name().prop_map(|n| (QType::CNAME, RData::CNAME(n))),
+        (name(), name(), any::<[u32; 5]>()).prop_map(|(mname, rname, values)| {
+            let [serial, refresh, retry, expire, minimum] = values;
+            (
+                QType::SOA,
+                RData::SOA {
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                },
+            )
+        }),
+        name().prop_map(|n| (QType::MB, RData::MB(n))),
+        name().prop_map(|n| (QType::MG, RData::MG(n))),
+        name().prop_map(|n| (QType::MR, RData::MR(n))),
+        vec(any::<u8>(), 0..64).prop_map(|d| (QType::NULL, RData::NULL(d))),
+        (any::<u32>(), any::<u8>(), vec(any::<u8>(), 0..32)).prop_map(
+            |(address, protocol, bitmap)| {
+                (
+                    QType::WKS,
+                    RData::WKS {
+                        address: Ipv4Addr::from(address),
+                        protocol,
+                        bitmap,
+                    },
+                )
+            }
+        ),
+        name().prop_map(|n| (QType::PTR, RData::PTR(n))),
+        (character_string(), character_string())
+            .prop_map(|(cpu, os)| (QType::HINFO, RData::HINFO { cpu, os })),
+        (name(), name())
+            .prop_map(|(rmailbx, emailbx)| (QType::MINFO, RData::MINFO { rmailbx, emailbx })),
+        (any::<u16>(), name()).prop_map(|(preference, exchange)| {
+            (
+                QType::MX,
+                RData::MX {
+                    preference,
+                    exchange,
+                },
+            )
+        }),
+        vec(character_string(), 0..4).prop_map(|s| (QType::TXT, RData::TXT(s))),
+        any::<u128>().prop_map(|a| (QType::AAAA, RData::AAAA(Ipv6Addr::from(a)))),
+        (any::<[u16; 3]>(), name()).prop_map(|([priority, weight, port], target)| {
+            (
+                QType::SRV,
+                RData::SRV {
+                    priority,
+                    weight,
+                    port,
+                    target,
+                },
+            )
+        }),
+        (
+            any::<[u16; 2]>(),
+            character_string(),
+            character_string(),
+            character_string(),
+            name()
+        )
+            .prop_map(
+                |([order, preference], flags, services, regexp, replacement)| {
+                    (
+                        QType::NAPTR,
+                        RData::NAPTR {
+                            order,
+                            preference,
+                            flags,
+                            services,
+                            regexp,
+                            replacement,
+                        },
+                    )
+                }
+            ),
+        (unknown_type, vec(any::<u8>(), 0..64)).prop_map(|(t, d)| (t, RData::Unknown(d))),
+    ]
+}
+
+fn record() -> impl Strategy<Value = Answer> {
+    (name(), rdata(), any::<u16>(), any::<u32>()).prop_map(
+        |(name, (answer_type, data), class, ttl)| Answer {
+            name,
+            answer_type,
+            // These are only typed in the Internet class, anywhere else they come back opaque
+            class: match answer_type {
+                QType::A | QType::WKS | QType::AAAA | QType::SRV => QClass::IN,
+                _ => QClass::from_u16(class),
+            },
+            ttl,
+            data,
+        },
+    )
+}
+
+fn question() -> impl Strategy<Value = Question> {
+    (name(), any::<u16>(), any::<u16>()).prop_map(|(name, question_type, class)| Question {
+        name,
+        question_type: QType::from_u16(question_type),
+        class: QClass::from_u16(class),
+    })
+}
+
+fn edns() -> impl Strategy<Value = Edns> {
+    let option =
+        (any::<u16>(), vec(any::<u8>(), 0..16)).prop_map(|(code, data)| EdnsOption { code, data });
+    (any::<u16>(), any::<u8>(), any::<bool>(), vec(option, 0..3)).prop_map(
+        |(udp_payload_size, version, dnssec_ok, options)| Edns {
+            udp_payload_size,
+            extended_rcode: 0,
+            version,
+            dnssec_ok,
+            options,
+        },
+    )
+}
+
+fn message() -> impl Strategy<Value = Message> {
+    let flags = any::<[bool; 8]>();
+    let sections = (
+        vec(question(), 0..3),
+        vec(record(), 0..4),
+        vec(record(), 0..4),
+        vec(record(), 0..4),
+    );
+    (
+        any::<u16>(),
+        flags,
+        0..16u8,
+        any::<u16>(),
+        proptest::option::of(edns()),
+        sections,
+    )
+        .prop_map(
+            |(id, flags, opcode, rcode, edns, (questions, answer, authority, additional))| {
+                // Without an OPT record only the 4 header bits of the rcode make it across
+                let rcode = Rcode::from_u16(match edns {
+                    Some(_) => rcode & 0xFFF,
+                    None => rcode & 0xF,
+                });
+                let edns = edns.map(|edns| Edns {
+                    extended_rcode: rcode.extended_bits(),
+                    ..edns
+                });
+                let [qr, aa, tc, rd, ra, z, ad, cd] = flags;
+                MessageBuilder::new()
+                    .id(id)
+                    .qr(if qr {
+                        QRIndicator::Response
+                    } else {
+                        QRIndicator::Query
+                    })
+                    .opcode(Opcode::from_u8(opcode))
+                    .authoritative_answer(aa)
+                    .truncation(tc)
+                    .recursion_desired(rd)
+                    .recursion_available(ra)
+                    .zero(z)
+                    .authentic_data(ad)
+                    .checking_disabled(cd)
+                    .rcode(rcode)
+                    .edns(edns)
+                    .questions(questions)
+                    .answers(answer)
+                    .authority(authority)
+                    .additional(additional)
+                    .build()
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn message_round_trips(message in message()) {
+        let bytes = message.as_bytes();
+        prop_assert_eq!(Message::parse_resolver_response(&bytes), Ok(message));
+    }
+
+    #[test]
+    fn lazy_view_matches_owned_message(message in message()) {
+        let bytes = message.as_bytes();
+        let view = MessageRef::parse(&bytes).unwrap();
+        let questions: Vec<Question> = view.questions().map(|q| q.to_question()).collect();
+        prop_assert_eq!(questions, message.questions);
+        prop_assert_eq!(view.answers().count(), message.answer.len());
+        prop_assert_eq!(view.authority().count(), message.authority.len());
+    }
+
+    #[test]
+    fn garbage_does_not_panic(bytes in vec(any::<u8>(), 0..512)) {
+        let _ = Message::parse_resolver_response(&bytes);
+    }
+}
+
+// A pointer back into its own name passes the check for forward pointers but would still loop.
+#[test]
+fn pointer_into_the_same_name_is_a_loop() {
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&[1, b'a', 0xC0, 12, 0, 1, 0, 1]);
+    assert_eq!(
+        MessageRef::parse(&packet).unwrap_err(),
+        ParseError::PointerLoop(12)
+    );
+}
+
+// An A record outside the Internet class has no fixed layout, so it is kept as it is.
+#[test]
+fn class_specific_types_are_opaque_in_other_classes() {
+    let mut packet = vec![0, 0, 0x80, 0, 0, 0, 0, 1, 0, 0, 0, 0];
+    // CH A record holding a 2 byte address followed by a name, as Chaosnet does
+    packet.extend_from_slice(&[0, 0, 1, 0, 3, 0, 0, 0, 60, 0, 5, 0x01, 0x02, 1, b'x', 0]);
+    let message = Message::parse_resolver_response(&packet).unwrap();
+    assert_eq!(message.answer[0].class, QClass::CH);
+    assert_eq!(
+        message.answer[0].data,
+        RData::Unknown(vec![1, 2, 1, b'x', 0])
+    );
+    assert_eq!(message.as_bytes(), packet);
+}
+
+#[test]
+fn extended_rcodes_need_an_opt_record() {
+    for rcode in [Rcode::BADVERS, Rcode::BADCOOKIE] {
+        let response = MessageBuilder::new().rcode(rcode).build();
+        let without_opt = Message::parse_resolver_response(&response.as_bytes()).unwrap();
+        assert_eq!(without_opt.header.response_code, Rcode::SERVFAIL);
+
+        let response = MessageBuilder::new()
+            .rcode(rcode)
+            .edns(Some(Edns::new(false)))
+            .build();
+        let with_opt = Message::parse_resolver_response(&response.as_bytes()).unwrap();
+        assert_eq!(with_opt.header.response_code, rcode);
+    }
+}
+
+// Running out of bytes is reported where the cut off question or record starts.
+#[test]
+fn truncated_entries_are_located() {
+    let question = [1, b'a', 0, 0, 1, 0, 1];
+    let mut packet = vec![0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+    packet.extend_from_slice(&question);
+    packet.extend_from_slice(&question[..5]);
+    assert_eq!(
+        MessageRef::parse(&packet).unwrap_err(),
+        ParseError::UnexpectedEnd(19)
+    );
+
+    let mut packet = vec![0, 0, 0x80, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+    packet.extend_from_slice(&question);
+    // The record's name points at the question, the TTL is cut short
+    packet.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0]);
+    assert_eq!(
+        MessageRef::parse(&packet).unwrap_err(),
+        ParseError::UnexpectedEnd(19)
+    );
+}
+
+fn query(name: &str, question_type: QType, class: QClass) -> Message {
+    MessageBuilder::new()
+        .id(0x1234)
+        .question(Question {
+            name: name.parse().unwrap(),
+            question_type,
+            class,
+        })
+        .build()
+}
+
+fn a_record(name: &str, last_octet: u8) -> Answer {
+    Answer {
+        name: name.parse().unwrap(),
+        answer_type: QType::A,
+        class: QClass::IN,
+        ttl: 60,
+        data: RData::A(Ipv4Addr::new(192, 0, 2, last_octet)),
+    }
+}
+
+#[test]
+fn only_matching_responses_are_accepted() {
+    let sent = query("www.example.com", QType::A, QClass::IN);
+    let accepted = |response: Message| {
+        let bytes = response.as_bytes();
+        MessageRef::parse(&bytes).unwrap().is_response_to(&sent)
+    };
+    let answer = || MessageBuilder::response_to(&sent);
+
+    assert!(accepted(answer().build()));
+    // Names are compared ignoring case (RFC 4343)
+    let upper = query("WWW.Example.COM", QType::A, QClass::IN);
+    assert!(accepted(MessageBuilder::response_to(&upper).build()));
+
+    assert!(!accepted(answer().id(0x1235).build()));
+    assert!(!accepted(answer().qr(QRIndicator::Query).build()));
+    for other in [
+        query("www.example.org", QType::A, QClass::IN),
+        query("www.example.com", QType::AAAA, QClass::IN),
+        query("www.example.com", QType::A, QClass::CH),
+    ] {
+        assert!(!accepted(MessageBuilder::response_to(&other).build()));
+    }
+    // Leaving out the question or adding one doesn't do either
+    assert!(!accepted(
+        MessageBuilder::new()
+            .id(0x1234)
+            .qr(QRIndicator::Response)
+            .build()
+    ));
+    assert!(!accepted(
+        answer()
+            .question(query("example.com", QType::A, QClass::IN).questions[0].clone())
+            .build()
+    ));
+}
+
+#[test]
+fn truncation_drops_rrsets_from_the_back() {
+    let sent = query("www.example.com", QType::A, QClass::IN);
+    let answer = [
+        a_record("www.example.com", 1),
+        a_record("www.example.com", 2),
+    ];
+    let authority = [Answer {
+        name: "example.com".parse().unwrap(),
+        answer_type: QType::NS,
+        class: QClass::IN,
+        ttl: 60,
+        data: RData::NS("ns1.example.com".parse().unwrap()),
+    }];
+    let additional = [
+        a_record("ns1.example.com", 3),
+        a_record("ns2.example.com", 4),
+    ];
+    // The response with only the first few records of each section
+    let without = |answers: usize, authorities: usize, additionals: usize| {
+        MessageBuilder::response_to(&sent)
+            .answers(answer[..answers].to_vec())
+            .authority(authority[..authorities].to_vec())
+            .additional(additional[..additionals].to_vec())
+            .build()
+    };
+    let full = without(2, 1, 2);
+    let truncated = |limit: usize| {
+        let mut buf = BytesMut::new();
+        full.emit_truncated(&mut buf, limit).unwrap();
+        assert!(buf.len() <= limit);
+        Message::parse_resolver_response(&buf).unwrap()
+    };
+    let size = |message: &Message| message.as_bytes().len();
+
+    assert_eq!(truncated(size(&full)), full);
+    assert_eq!(truncated(size(&full) - 1), without(2, 1, 1));
+    assert_eq!(truncated(size(&without(2, 1, 1)) - 1), without(2, 1, 0));
+    assert_eq!(truncated(size(&without(2, 1, 0)) - 1), without(2, 0, 0));
+
+    // The answer RRset goes as a whole and only now TC is set
+    let mut only_question = without(0, 0, 0);
+    only_question.header.truncation = true;
+    assert_eq!(truncated(size(&without(2, 0, 0)) - 1), only_question);
+}
+
+#[test]
+fn requests_are_validated() {
+    let valid = query("www.example.com", QType::A, QClass::IN);
+    assert_eq!(valid.response_rcode(), Rcode::NOERROR);
+
+    let no_question = MessageBuilder::new().id(1).build();
+    assert_eq!(no_question.response_rcode(), Rcode::FORMERR);
+
+    let chaos = query("version.bind", QType::TXT, QClass::CH);
+    assert_eq!(chaos.response_rcode(), Rcode::REFUSED);
+
+    let mut notify = valid.clone();
+    notify.header.opcode = Opcode::NOTIFY;
+    assert_eq!(notify.response_rcode(), Rcode::NOTIMP);
+
+    let mut future_edns = valid.clone();
+    future_edns.edns = Some(Edns {
+        version: 1,
+        ..Edns::new(false)
+    });
+    assert_eq!(future_edns.response_rcode(), Rcode::BADVERS);
+}
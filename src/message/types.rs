@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, Copy)]
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum QRIndicator {
     Query = 0,
     Response = 1,
@@ -14,68 +16,281 @@ impl QRIndicator {
     }
 }
 
+// Opcode is the kind of query a message carries, 4 bits in the header.
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Opcode {
+    // A standard query
+    QUERY,
+    // An inverse query (Obsolete, RFC 3425)
+    IQUERY,
+    // A server status request
+    STATUS,
+    // A zone change notification (RFC 1996)
+    NOTIFY,
+    // A dynamic update (RFC 2136)
+    UPDATE,
+    // DNS stateful operations (RFC 8490)
+    DSO,
+    // Any opcode we have no name for, kept by its number
+    Unknown(u8),
+}
+
+impl Opcode {
+    pub(crate) fn as_u8(self) -> u8 {
+        match self {
+            Self::QUERY => 0,
+            Self::IQUERY => 1,
+            Self::STATUS => 2,
+            Self::NOTIFY => 4,
+            Self::UPDATE => 5,
+            Self::DSO => 6,
+            Self::Unknown(value) => value,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::QUERY,
+            1 => Self::IQUERY,
+            2 => Self::STATUS,
+            4 => Self::NOTIFY,
+            5 => Self::UPDATE,
+            6 => Self::DSO,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+// Rcode is the outcome of a query. Values above 15 don't fit the 4 header bits, their upper
+// 8 bits travel in the OPT record (RFC 6891 6.1.3).
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rcode {
+    // No error condition
+    NOERROR,
+    // The server was unable to interpret the query
+    FORMERR,
+    // The server was unable to process the query due to a problem with the server
+    SERVFAIL,
+    // The domain name referenced in the query does not exist
+    NXDOMAIN,
+    // The server does not support the requested kind of query
+    NOTIMP,
+    // The server refuses to perform the specified operation for policy reasons
+    REFUSED,
+    // Some name that ought not to exist does exist (RFC 2136)
+    YXDOMAIN,
+    // Some RRset that ought not to exist does exist (RFC 2136)
+    YXRRSET,
+    // Some RRset that ought to exist does not exist (RFC 2136)
+    NXRRSET,
+    // The server is not authoritative for the zone named (RFC 2136)
+    NOTAUTH,
+    // A name is not within the zone (RFC 2136)
+    NOTZONE,
+    // DSO-TYPE not implemented (RFC 8490)
+    DSOTYPENI,
+    // Bad OPT version, shares its value with TSIG's BADSIG (RFC 6891)
+    BADVERS,
+    // Key not recognized (RFC 8945)
+    BADKEY,
+    // Signature out of time window (RFC 8945)
+    BADTIME,
+    // Bad TKEY mode (RFC 2930)
+    BADMODE,
+    // Duplicate key name (RFC 2930)
+    BADNAME,
+    // Algorithm not supported (RFC 2930)
+    BADALG,
+    // Bad truncation (RFC 8945)
+    BADTRUNC,
+    // Bad or missing server cookie (RFC 7873)
+    BADCOOKIE,
+    // Any rcode we have no name for, kept by its number
+    Unknown(u16),
+}
+
+impl Rcode {
+    pub(crate) fn as_u16(self) -> u16 {
+        match self {
+            Self::NOERROR => 0,
+            Self::FORMERR => 1,
+            Self::SERVFAIL => 2,
+            Self::NXDOMAIN => 3,
+            Self::NOTIMP => 4,
+            Self::REFUSED => 5,
+            Self::YXDOMAIN => 6,
+            Self::YXRRSET => 7,
+            Self::NXRRSET => 8,
+            Self::NOTAUTH => 9,
+            Self::NOTZONE => 10,
+            Self::DSOTYPENI => 11,
+            Self::BADVERS => 16,
+            Self::BADKEY => 17,
+            Self::BADTIME => 18,
+            Self::BADMODE => 19,
+            Self::BADNAME => 20,
+            Self::BADALG => 21,
+            Self::BADTRUNC => 22,
+            Self::BADCOOKIE => 23,
+            Self::Unknown(value) => value,
+        }
+    }
+
+    pub(crate) fn from_u16(value: u16) -> Self {
+        match value {
+            0 => Self::NOERROR,
+            1 => Self::FORMERR,
+            2 => Self::SERVFAIL,
+            3 => Self::NXDOMAIN,
+            4 => Self::NOTIMP,
+            5 => Self::REFUSED,
+            6 => Self::YXDOMAIN,
+            7 => Self::YXRRSET,
+            8 => Self::NXRRSET,
+            9 => Self::NOTAUTH,
+            10 => Self::NOTZONE,
+            11 => Self::DSOTYPENI,
+            16 => Self::BADVERS,
+            17 => Self::BADKEY,
+            18 => Self::BADTIME,
+            19 => Self::BADMODE,
+            20 => Self::BADNAME,
+            21 => Self::BADALG,
+            22 => Self::BADTRUNC,
+            23 => Self::BADCOOKIE,
+            _ => Self::Unknown(value),
+        }
+    }
+
+    // The lower 4 bits, which go into the header.
+    pub(crate) fn header_bits(self) -> u8 {
+        (self.as_u16() & 0xF) as u8
+    }
+
+    // The upper 8 bits, which go into the OPT record.
+    pub(crate) fn extended_bits(self) -> u8 {
+        (self.as_u16() >> 4) as u8
+    }
+
+    pub(crate) fn from_parts(header_bits: u8, extended_bits: u8) -> Self {
+        Self::from_u16((extended_bits as u16) << 4 | (header_bits & 0xF) as u16)
+    }
+}
+
 // QType fields are whats called TYPES when used in resource records.  Note that these types are a
 // subset of what's oficially called QTYPEs.
 
-#[derive(Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum QType {
     // A host Address
-    A = 1,
+    A,
     // An authoritative name server
-    NS = 2,
+    NS,
     // A mail destination (Obsolete use MX)
-    MD = 3,
+    MD,
     // A mail forwarder (Obsolete use MX)
-    MF = 4,
+    MF,
     // The canonical name for an alias
-    CNAME = 5,
+    CNAME,
     // Marks the start of a zone of authority
-    SOA = 6,
+    SOA,
     // A mailbox domain name (EXPERIMENTAL)
-    MB = 7,
+    MB,
     // A mail group member (EXPERIMENTAL)
-    MG = 8,
+    MG,
     // A mail rename domain server (EXPERIMENTAL)
-    MR = 9,
+    MR,
     //  a null RR (EXPERIMENTAL)
-    NULL = 10,
+    NULL,
     //  A well known service description
-    WKS = 11,
+    WKS,
     //  A domain name pointer
-    PTR = 12,
+    PTR,
     // Host information
-    HINFO = 13,
+    HINFO,
     // Mailbox or mail list information
-    MINFO = 14,
+    MINFO,
     // Mail exchange
-    MX = 15,
+    MX,
     // Text strings
-    TXT = 16,
+    TXT,
+    // An IPv6 host address (RFC 3596)
+    AAAA,
+    // The location of a service (RFC 2782)
+    SRV,
+    // A rewrite rule for dynamic delegation (RFC 3403)
+    NAPTR,
+    // The EDNS pseudo-record, only ever found in the additional section (RFC 6891)
+    OPT,
+    // Any type we have no name for, kept by its number
+    Unknown(u16),
 }
 
 impl QType {
     pub(crate) fn as_u16(self) -> u16 {
-        self as u16
+        match self {
+            Self::A => 1,
+            Self::NS => 2,
+            Self::MD => 3,
+            Self::MF => 4,
+            Self::CNAME => 5,
+            Self::SOA => 6,
+            Self::MB => 7,
+            Self::MG => 8,
+            Self::MR => 9,
+            Self::NULL => 10,
+            Self::WKS => 11,
+            Self::PTR => 12,
+            Self::HINFO => 13,
+            Self::MINFO => 14,
+            Self::MX => 15,
+            Self::TXT => 16,
+            Self::AAAA => 28,
+            Self::SRV => 33,
+            Self::NAPTR => 35,
+            Self::OPT => 41,
+            Self::Unknown(value) => value,
+        }
     }
-    pub(crate) fn from_u16(value: u16) -> Option<Self> {
+
+    pub(crate) fn from_u16(value: u16) -> Self {
         match value {
-            1 => Some(Self::A),
-            2 => Some(Self::NS),
-            3 => Some(Self::MD),
-            4 => Some(Self::MF),
-            5 => Some(Self::CNAME),
-            6 => Some(Self::SOA),
-            7 => Some(Self::MB),
-            8 => Some(Self::MG),
-            9 => Some(Self::MR),
-            10 => Some(Self::NULL),
-            11 => Some(Self::WKS),
-            12 => Some(Self::PTR),
-            13 => Some(Self::HINFO),
-            14 => Some(Self::MINFO),
-            15 => Some(Self::MX),
-            16 => Some(Self::TXT),
-            _ => None,
+            1 => Self::A,
+            2 => Self::NS,
+            3 => Self::MD,
+            4 => Self::MF,
+            5 => Self::CNAME,
+            6 => Self::SOA,
+            7 => Self::MB,
+            8 => Self::MG,
+            9 => Self::MR,
+            10 => Self::NULL,
+            11 => Self::WKS,
+            12 => Self::PTR,
+            13 => Self::HINFO,
+            14 => Self::MINFO,
+            15 => Self::MX,
+            16 => Self::TXT,
+            28 => Self::AAAA,
+            33 => Self::SRV,
+            35 => Self::NAPTR,
+            41 => Self::OPT,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+// Mnemonic where we have one, the generic TYPENN form from RFC 3597 otherwise.
+impl fmt::Display for QType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(value) => write!(f, "TYPE{}", value),
+            known => write!(f, "{:?}", known),
         }
     }
 }
@@ -83,30 +298,48 @@ impl QType {
 // QClass fields appear in resource records under the official name Class. Note that these classes
 // are a subset of what's oficially called QCLASS.
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum QClass {
     // The internet
-    IN = 1,
+    IN,
     // The CSNET class (Obsolete - used only for examples in some obsolete RFCs)
-    CS = 2,
+    CS,
     // The CHAOS class
-    CH = 3,
+    CH,
     // Hesiod
-    HS = 4,
+    HS,
+    // Any class we have no name for, kept by its number
+    Unknown(u16),
 }
 
 impl QClass {
     pub(crate) fn as_u16(self) -> u16 {
-        self as u16
+        match self {
+            Self::IN => 1,
+            Self::CS => 2,
+            Self::CH => 3,
+            Self::HS => 4,
+            Self::Unknown(value) => value,
+        }
     }
 
-    pub(crate) fn from_u16(value: u16) -> Option<Self> {
+    pub(crate) fn from_u16(value: u16) -> Self {
         match value {
-            1 => Some(Self::IN),
-            2 => Some(Self::CS),
-            3 => Some(Self::CH),
-            4 => Some(Self::HS),
-            _ => None,
+            1 => Self::IN,
+            2 => Self::CS,
+            3 => Self::CH,
+            4 => Self::HS,
+            _ => Self::Unknown(value),
+        }
+    }
+}
+
+// Mnemonic where we have one, the generic CLASSNN form from RFC 3597 otherwise.
+impl fmt::Display for QClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(value) => write!(f, "CLASS{}", value),
+            known => write!(f, "{:?}", known),
         }
     }
 }
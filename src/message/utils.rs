@@ -1,51 +1,63 @@
-use anyhow::{anyhow, Context, Result};
-use log::debug;
+use super::name::DomainName;
+use bytes::{BufMut, BytesMut};
+use std::collections::HashMap;
 
-pub trait LabelDecompression {
-    fn parse_label(buf: &[u8], pos: Option<usize>) -> Result<(String, usize)> {
-        let mut name = String::new();
-        let mut pos = pos.unwrap_or(12);
-        let mut compression: bool = false;
-        debug!("Starting parse_label at position: {}", pos);
+// Longest name on the wire, length octets and the root label included (RFC 1035 2.3.4)
+pub(crate) const MAX_NAME_LENGTH: usize = 255;
+// Longest single label (RFC 1035 2.3.4)
+pub(crate) const MAX_LABEL_LENGTH: usize = 63;
+// Every pointer has to land before where the previous one did, which rules out loops. This bounds
+// the work for long chains of them.
+pub(crate) const MAX_POINTER_HOPS: usize = 64;
 
-        while pos < buf.len() && buf[pos] != 0 {
-            debug!("At position {}: buf[{}] = {:02X}", pos, pos, buf[pos]);
+// Writes names into a message that is being assembled. Every suffix written is remembered by its
+// offset so later occurrences can be replaced with a compression pointer (RFC 1035 4.1.4).
+pub(crate) struct LabelCompression {
+    offsets: Option<HashMap<DomainName, u16>>,
+    // Where the message starts in the buffer, offsets are counted from there
+    start: usize,
+}
 
-            if !name.is_empty() {
-                name.push('.');
-            }
+impl Default for LabelCompression {
+    fn default() -> Self {
+        Self::starting_at(0)
+    }
+}
 
-            compression = (buf[pos] >> 6) & 0b11 == 0b11;
-            debug!("Compression flag: {}", compression);
+impl LabelCompression {
+    // For a message written from `start` on, e.g. after a TCP length prefix.
+    pub fn starting_at(start: usize) -> Self {
+        LabelCompression {
+            offsets: Some(HashMap::new()),
+            start,
+        }
+    }
 
-            if compression {
-                let compression_pointer = ((buf[pos] & 0x3F) as u16) << 8 | buf[pos + 1] as u16;
-                pos += 2;
-                debug!("Following compression pointer to: {}", compression_pointer);
-                let (label, _) = Self::parse_label(buf, Some(compression_pointer as usize))?;
-                name.push_str(&label);
-            } else {
-                let len = buf[pos] as usize;
-                pos += 1;
-                debug!("Label length: {}", len);
-                if pos + len > buf.len() {
-                    return Err(anyhow!("Label extends beyond buffer length"));
+    // Writes names in full, for bytes that are not going to be part of a complete message.
+    pub fn disabled() -> Self {
+        LabelCompression {
+            offsets: None,
+            start: 0,
+        }
+    }
+
+    // Writes `name` at the end of `buf`, which must hold the message from `start` on.
+    pub fn write_label(&mut self, buf: &mut BytesMut, name: &DomainName) {
+        for (label, suffix) in name.labels().iter().zip(name.ancestors()) {
+            if let Some(offsets) = self.offsets.as_mut() {
+                if let Some(offset) = offsets.get(&suffix) {
+                    buf.put_u16(0xC000 | offset);
+                    return;
+                }
+                // Pointers only have 14 bits, anything further in can't be referenced
+                let offset = buf.len() - self.start;
+                if offset <= 0x3FFF {
+                    offsets.insert(suffix, offset as u16);
                 }
-                let label = std::str::from_utf8(&buf[pos..pos + len])
-                    .context("Should be utf-8 encoded.")?
-                    .to_string();
-                pos += len;
-                name.push_str(&label);
-                debug!("Parsed label: '{}'", label);
             }
+            buf.put_u8(label.len() as u8);
+            buf.put_slice(label);
         }
-        if !compression {
-            pos += 1;
-        }
-        debug!(
-            "Completed label parsing with name: '{}', next position: {}",
-            name, pos
-        );
-        Ok((name, pos))
+        buf.put_u8(0);
     }
 }
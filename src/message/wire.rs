@@ -0,0 +1,183 @@
+use super::error::ParseError;
+use super::name::DomainName;
+use super::utils::{MAX_LABEL_LENGTH, MAX_NAME_LENGTH, MAX_POINTER_HOPS};
+use nom::bytes::complete::take;
+use nom::combinator::{map, recognize};
+use nom::error::ErrorKind;
+use nom::multi::fold_many_m_n;
+use nom::number::complete::be_u8;
+use nom::{Err, IResult};
+
+// Building blocks of the wire decoder. Every parser takes the remaining input, which is always a
+// slice of one complete message: compression pointers are resolved against that message and error
+// offsets are recovered from where the slice starts within it. Nothing here knows about UDP, a
+// TCP stream or a zone transfer only has to cut out each message before handing it to `Wire`.
+
+pub(crate) type WireResult<'a, T> = IResult<&'a [u8], T, WireError<'a>>;
+
+#[derive(Debug)]
+pub(crate) enum WireError<'a> {
+    // A nom combinator gave up on this input, which with complete parsers means it ran out
+    Nom(&'a [u8]),
+    // One of our own checks failed, the offset is already known
+    Parse(ParseError),
+}
+
+impl<'a> nom::error::ParseError<&'a [u8]> for WireError<'a> {
+    fn from_error_kind(input: &'a [u8], _kind: ErrorKind) -> Self {
+        WireError::Nom(input)
+    }
+
+    // The innermost error is the precise one, what the combinators around it add is noise
+    fn append(_input: &'a [u8], _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+// Stops parsing with `error`, alternatives are not tried.
+pub(crate) fn fail<'a, T>(error: ParseError) -> WireResult<'a, T> {
+    Err(Err::Failure(WireError::Parse(error)))
+}
+
+// Consumes exactly `n` entries and returns the bytes they took up, without collecting them.
+pub(crate) fn skip_n<'a, T>(
+    n: u16,
+    parser: impl FnMut(&'a [u8]) -> WireResult<'a, T>,
+) -> impl FnMut(&'a [u8]) -> WireResult<'a, &'a [u8]> {
+    recognize(fold_many_m_n(
+        n as usize,
+        n as usize,
+        parser,
+        || (),
+        |(), _| (),
+    ))
+}
+
+// One step through a name as it is on the wire.
+pub(crate) enum Label<'a> {
+    Data(&'a [u8]),
+    Pointer(usize),
+    End,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Wire<'a> {
+    msg: &'a [u8],
+}
+
+impl<'a> Wire<'a> {
+    pub fn new(msg: &'a [u8]) -> Self {
+        Wire { msg }
+    }
+
+    // Where `input` starts in the message.
+    pub fn offset(&self, input: &'a [u8]) -> usize {
+        (input.as_ptr() as usize).saturating_sub(self.msg.as_ptr() as usize)
+    }
+
+    // The message from `offset` on, for following a pointer that has already been validated.
+    pub fn at(&self, offset: usize) -> &'a [u8] {
+        &self.msg[offset.min(self.msg.len())..]
+    }
+
+    // Turns a failed parse into the error reported for the message.
+    pub fn error(&self, e: Err<WireError<'a>>) -> ParseError {
+        match e {
+            Err::Incomplete(_) => ParseError::UnexpectedEnd(self.msg.len()),
+            Err::Error(WireError::Nom(input)) | Err::Failure(WireError::Nom(input)) => {
+                ParseError::UnexpectedEnd(self.offset(input))
+            }
+            Err::Error(WireError::Parse(e)) | Err::Failure(WireError::Parse(e)) => e,
+        }
+    }
+
+    // Runs `parser` on one question or record. Should the message end before the entry does, the
+    // error points at where the entry starts rather than at whichever field ran out.
+    pub fn entry<T>(
+        &self,
+        input: &'a [u8],
+        parser: fn(Wire<'a>, &'a [u8]) -> WireResult<'a, T>,
+    ) -> WireResult<'a, T> {
+        parser(*self, input).map_err(|e| match e {
+            Err::Error(WireError::Nom(_)) | Err::Failure(WireError::Nom(_)) => Err::Failure(
+                WireError::Parse(ParseError::UnexpectedEnd(self.offset(input))),
+            ),
+            e => e,
+        })
+    }
+
+    pub fn label(&self, input: &'a [u8]) -> WireResult<'a, Label<'a>> {
+        let (rest, len) = be_u8(input)?;
+        match len >> 6 {
+            0b11 => map(be_u8, |low| {
+                Label::Pointer(((len & 0x3F) as usize) << 8 | low as usize)
+            })(rest),
+            _ if len == 0 => Ok((rest, Label::End)),
+            // The 0b01 and 0b10 prefixes are covered here as well
+            _ if len as usize > MAX_LABEL_LENGTH => fail(ParseError::LabelTooLong {
+                offset: self.offset(input),
+                length: len as usize,
+            }),
+            _ => map(take(len), Label::Data)(rest),
+        }
+    }
+
+    // Validates the name at the start of `input`, handing each label to `on_label` as it goes.
+    // Nothing is allocated, so this also serves to skip over names.
+    pub fn walk_name(
+        &self,
+        input: &'a [u8],
+        mut on_label: impl FnMut(&'a [u8]),
+    ) -> WireResult<'a, ()> {
+        let start = self.offset(input);
+        // Where the labels currently being read start, either the name itself or the last target
+        let mut segment = start;
+        let mut at = input;
+        // Where parsing continues once the name is done, set by the first pointer followed
+        let mut rest = None;
+        let mut hops = 0;
+        let mut wire_length = 1;
+
+        loop {
+            let (next, label) = self.label(at)?;
+            match label {
+                Label::Pointer(target) => {
+                    let offset = self.offset(at);
+                    if target >= offset {
+                        return fail(ParseError::ForwardPointer { offset, target });
+                    }
+                    // Pointing back into the labels just read would come around to this pointer
+                    // again, so every target has to be before the previous one
+                    if target >= segment {
+                        return fail(ParseError::PointerLoop(start));
+                    }
+                    segment = target;
+                    hops += 1;
+                    if hops > MAX_POINTER_HOPS {
+                        return fail(ParseError::PointerLoop(start));
+                    }
+                    rest.get_or_insert(next);
+                    at = self.at(target);
+                }
+                Label::Data(label) => {
+                    wire_length += label.len() + 1;
+                    if wire_length > MAX_NAME_LENGTH {
+                        return fail(ParseError::NameTooLong(start));
+                    }
+                    on_label(label);
+                    at = next;
+                }
+                Label::End => return Ok((rest.unwrap_or(next), ())),
+            }
+        }
+    }
+
+    pub fn name(&self, input: &'a [u8]) -> WireResult<'a, DomainName> {
+        let mut labels = vec![];
+        let (rest, ()) = self.walk_name(input, |label| labels.push(label.to_vec()))?;
+        match DomainName::from_labels(labels) {
+            Ok(name) => Ok((rest, name)),
+            Err(e) => fail(e),
+        }
+    }
+}
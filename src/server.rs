@@ -0,0 +1,715 @@
+use crate::message::builder::MessageBuilder;
+use crate::message::edns::{Edns, DEFAULT_UDP_PAYLOAD_SIZE, MAX_UDP_PAYLOAD_SIZE};
+use crate::message::header::Header;
+use crate::message::message::{AsBytes, Message};
+use crate::message::message_ref::MessageRef;
+use crate::message::types::{QRIndicator, Rcode};
+use crate::Args;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{BufMut, BytesMut};
+use clap::ValueEnum;
+use log::{debug, error, info, warn};
+use rand::Rng;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep_until, timeout, Instant};
+
+// Most queries of one TCP connection waiting for their response. Never more than half of the
+// in-flight budget either, so that a single client can't take up all of it.
+const MAX_PENDING_PER_CONNECTION: usize = 16;
+
+// How long to wait before accepting again after an error, doubled while errors keep coming.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+// What happens to a query that arrives while the in-flight cap is reached.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum WhenBusy {
+    // Ignore it, the client will retry
+    Drop,
+    // Answer right away with SERVFAIL
+    Servfail,
+}
+
+// Everything the listeners share. Each query is answered in its own task, the semaphores cap how
+// many of those run at once and how many TCP connections are open.
+pub(crate) struct Server {
+    args: Args,
+    // Looked up once at startup, None answers locally
+    resolver: Option<SocketAddr>,
+    in_flight: Arc<Semaphore>,
+    connections: Arc<Semaphore>,
+    // Queries that got SERVFAIL because the upstream couldn't be reached or made no sense
+    upstream_failures: AtomicU64,
+}
+
+impl Server {
+    pub async fn new(args: Args) -> Result<Self> {
+        let resolver = if args.resolver.is_empty() {
+            None
+        } else {
+            let resolver = lookup_host(&args.resolver)
+                .await
+                .context("Failed looking up the resolver")?
+                .next()
+                .context("The resolver has no address")?;
+            info!("Forwarding queries to {}", resolver);
+            Some(resolver)
+        };
+        Ok(Server {
+            resolver,
+            in_flight: Arc::new(Semaphore::new(args.max_in_flight)),
+            connections: Arc::new(Semaphore::new(args.max_connections)),
+            upstream_failures: AtomicU64::new(0),
+            args,
+        })
+    }
+
+    pub async fn serve_udp(self: Arc<Self>, socket: UdpSocket) {
+        let socket = Arc::new(socket);
+        let mut buf = [0; MAX_UDP_PAYLOAD_SIZE as usize];
+
+        loop {
+            let (size, source) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Error receiving data: {}", e);
+                    break;
+                }
+            };
+            debug!(
+                "Received {} bytes from {}: {:?}",
+                size,
+                source,
+                &buf[..size]
+            );
+            let request = buf[..size].to_vec();
+
+            let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+                self.reject_busy(&socket, &request, source).await;
+                continue;
+            };
+            let server = self.clone();
+            let socket = socket.clone();
+            tokio::spawn(async move {
+                if let Some((response, max_size)) = server.handle(&request, source).await {
+                    send_udp(&socket, &response, max_size, source).await;
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    pub async fn serve_tcp(self: Arc<Self>, listener: TcpListener) {
+        let mut backoff = ACCEPT_BACKOFF;
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Usually the peer gave up already or we are out of file descriptors, neither
+                    // is a reason to stop listening. Retrying right away would only spin though.
+                    error!(
+                        "Error accepting connection, retrying in {:?}: {}",
+                        backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            backoff = ACCEPT_BACKOFF;
+            let Ok(permit) = self.connections.clone().try_acquire_owned() else {
+                warn!("Too many open connections, closing the one from {}", peer);
+                continue;
+            };
+            debug!("Accepted connection from {}", peer);
+            tokio::spawn(self.clone().serve_connection(stream, peer, permit));
+        }
+    }
+
+    // Queries on a connection are answered concurrently and each response is written as soon as
+    // it is ready, so responses can go out in a different order than the queries came in
+    // (RFC 7766 6.2.1.1). The connection is closed once the client has been idle for too long
+    // and everything it asked for has been answered, or right away if it stops reading.
+    async fn serve_connection(
+        self: Arc<Self>,
+        stream: TcpStream,
+        peer: SocketAddr,
+        permit: OwnedSemaphorePermit,
+    ) {
+        let (mut reader, mut writer) = stream.into_split();
+        let idle_timeout = Duration::from_secs(self.args.tcp_idle_timeout);
+        let (responses, mut outgoing) = mpsc::channel::<BytesMut>(MAX_PENDING_PER_CONNECTION);
+        let writing = tokio::spawn(async move {
+            while let Some(frame) = outgoing.recv().await {
+                match timeout(idle_timeout, writer.write_all(&frame)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        error!("Failed to send response to {}: {}", peer, e);
+                        break;
+                    }
+                    Err(_) => {
+                        warn!("{} stopped reading responses, closing the connection", peer);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let pending = Arc::new(Semaphore::new(
+            (self.args.max_in_flight / 2).clamp(1, MAX_PENDING_PER_CONNECTION),
+        ));
+        loop {
+            // Once the writer has given up there is no point in reading any further
+            let request = tokio::select! {
+                read = timeout(idle_timeout, read_frame(&mut reader)) => match read {
+                    Ok(Ok(Some(request))) => request,
+                    Ok(Ok(None)) => break,
+                    Ok(Err(e)) => {
+                        error!("Error receiving data from {}: {}", peer, e);
+                        break;
+                    }
+                    Err(_) => {
+                        debug!("Closing idle connection from {}", peer);
+                        break;
+                    }
+                },
+                _ = responses.closed() => break,
+            };
+            debug!(
+                "Received {} bytes from {}: {:?}",
+                request.len(),
+                peer,
+                request
+            );
+
+            // No need to turn anyone away here, not reading any further slows the client down
+            let permits = async {
+                let pending = pending.clone().acquire_owned().await;
+                let in_flight = self.in_flight.clone().acquire_owned().await;
+                pending
+                    .and_then(|pending| Ok((pending, in_flight?)))
+                    .expect("The semaphores are never closed")
+            };
+            let (pending_permit, query_permit) = tokio::select! {
+                permits = permits => permits,
+                _ = responses.closed() => break,
+            };
+            let server = self.clone();
+            let responses = responses.clone();
+            tokio::spawn(async move {
+                let response = server.handle(&request, peer).await;
+                // Only answering counts towards the in-flight cap, waiting for a slow reader
+                // is up to the connection's own budget
+                drop(query_permit);
+                if let Some((response, _)) = response {
+                    send_tcp(&responses, &response, peer).await;
+                }
+                drop(pending_permit);
+            });
+        }
+
+        // The writer finishes once the last pending query has dropped its sender
+        drop(responses);
+        let _ = writing.await;
+        drop(permit);
+        debug!("Closed connection from {}", peer);
+    }
+
+    async fn reject_busy(&self, socket: &UdpSocket, request: &[u8], source: SocketAddr) {
+        match self.args.when_busy {
+            WhenBusy::Drop => {
+                warn!(
+                    "Too many queries in flight, dropping request from {}",
+                    source
+                );
+            }
+            WhenBusy::Servfail => {
+                // Whatever would be dropped when not busy is dropped here too, responses above all
+                let Ok(request) = parse_request(request, source) else {
+                    return;
+                };
+                warn!(
+                    "Too many queries in flight, answering {} with SERVFAIL",
+                    source
+                );
+                let response = MessageBuilder::response_to(&request)
+                    .rcode(Rcode::SERVFAIL)
+                    .build();
+                send_udp(socket, &response, request.max_response_size(), source).await;
+            }
+        }
+    }
+
+    // The response to a raw request along with the most the client can take of it, None if the
+    // request gets no response at all.
+    async fn handle(&self, buf: &[u8], source: SocketAddr) -> Option<(Message, usize)> {
+        let request = match parse_request(buf, source) {
+            Ok(request) => request,
+            Err(header) => {
+                // Nothing can be said about the request beyond its header
+                let response = MessageBuilder::response_to_header(&header?)
+                    .rcode(Rcode::FORMERR)
+                    .build();
+                return Some((response, DEFAULT_UDP_PAYLOAD_SIZE as usize));
+            }
+        };
+
+        let max_size = request.max_response_size();
+        let response = if request.response_rcode() != Rcode::NOERROR {
+            info!("Rejecting request with {:?}", request.response_rcode());
+            request.create_answerless_response()
+        } else if let Some(resolver) = self.resolver {
+            info!("Querying resolver");
+            match resolve_query(&self.args, resolver, &request).await {
+                Ok(r) => r,
+                Err(e) => {
+                    let failures = self.upstream_failures.fetch_add(1, Ordering::Relaxed) + 1;
+                    error!(
+                        "Resolving request {} from {} failed ({} upstream failures so far): {:#}",
+                        request.header.id, source, failures, e
+                    );
+                    MessageBuilder::response_to(&request)
+                        .rcode(Rcode::SERVFAIL)
+                        .build()
+                }
+            }
+        } else {
+            info!("Creating local response.");
+            request.create_response()
+        };
+        Some((response, max_size))
+    }
+}
+
+// Turns a packet into a request worth answering. If it isn't one the error holds the header to
+// answer FORMERR to, None if the packet gets no answer at all.
+fn parse_request(buf: &[u8], source: SocketAddr) -> Result<Message, Option<Header>> {
+    // Without a header there isn't even an id to answer to
+    let Ok((_, header)) = Header::parse(buf) else {
+        error!("Dropping {} byte packet from {}", buf.len(), source);
+        return Err(None);
+    };
+    // Answering responses could get two servers to bounce errors back and forth forever
+    if header.qr == QRIndicator::Response {
+        warn!("Dropping response {} received from {}", header.id, source);
+        return Err(None);
+    }
+
+    // Validated in place, the owned message is only built once everything checks out
+    let request = MessageRef::parse(buf).and_then(|request| {
+        request.questions().for_each(|question| {
+            debug!(
+                "Question from {} in request {}: {} {} {}",
+                source, header.id, question.name, question.class, question.question_type
+            )
+        });
+        request.to_message()
+    });
+    request.map_err(|e| {
+        error!("Malformed request {} from {}: {}", header.id, source, e);
+        Some(header)
+    })
+}
+
+// One message off a TCP stream, None if the client closed the connection in between messages.
+// Closing it anywhere else, even inside the length, cuts a message off and is an error.
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 2];
+    if reader.read(&mut len[..1]).await? == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut len[1..]).await?;
+    let mut buf = vec![0; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut buf).await?;
+    Ok(Some(buf))
+}
+
+// Over TCP every response is prefixed with its length and can be as large as that allows.
+async fn send_tcp(responses: &mpsc::Sender<BytesMut>, response: &Message, dest: SocketAddr) {
+    let mut buf = BytesMut::new();
+    buf.put_u16(0);
+    if let Err(e) = response.emit_truncated(&mut buf, u16::MAX as usize) {
+        error!("Dropping response to {}: {}", dest, e);
+        return;
+    }
+    let len = (buf.len() - 2) as u16;
+    buf[..2].copy_from_slice(&len.to_be_bytes());
+    // Fails only if the connection broke, which has been logged already
+    let _ = responses.send(buf).await;
+}
+
+// All UDP responses go out through here, cut down to what the client accepts.
+async fn send_udp(socket: &UdpSocket, response: &Message, max_size: usize, dest: SocketAddr) {
+    let mut buf = BytesMut::with_capacity(max_size);
+    if let Err(e) = response.emit_truncated(&mut buf, max_size) {
+        error!("Dropping response to {}: {}", dest, e);
+        return;
+    }
+    match socket.send_to(&buf, dest).await {
+        Ok(bytes_sent) => debug!("Sent {} bytes in response to {}", bytes_sent, dest),
+        Err(e) => error!("Failed to send response: {}", e),
+    }
+}
+
+// Gives up once the whole query took longer than the deadline, however many retries are left.
+async fn resolve_query(args: &Args, resolver: SocketAddr, query: &Message) -> Result<Message> {
+    let deadline = Duration::from_millis(args.upstream_deadline);
+    timeout(deadline, forward_query(args, resolver, query))
+        .await
+        .with_context(|| format!("No answer from the resolver within {:?}", deadline))?
+}
+
+async fn forward_query(args: &Args, resolver: SocketAddr, query: &Message) -> Result<Message> {
+    info!("Resolving Query");
+    // Upstream always gets our own OPT record, only the DO bit is passed on from the client
+    let dnssec_ok = query.edns.as_ref().is_some_and(|edns| edns.dnssec_ok);
+    let mut response_buf = vec![0u8; MAX_UDP_PAYLOAD_SIZE as usize];
+    let mut response = MessageBuilder::response_to(query);
+    let mut rcode = Rcode::NOERROR;
+    // AD is only passed on to clients that show they understand it (RFC 6840 5.7), and only if
+    // every upstream answer had it set
+    let mut authentic_data = query.header.authentic_data || dnssec_ok;
+    // Only left set if an answer was still truncated after asking again over TCP
+    let mut truncation = false;
+
+    for mut upstream in query.split(&Edns::new(dnssec_ok)) {
+        // Nothing the client picked may be used against us, least of all the id
+        upstream.header.id = rand::random();
+        let socket = upstream_socket(resolver).await?;
+        // Names in the upstream packet are decompressed while parsing and get re-encoded
+        // against our own response when it is serialized.
+        let mut rsp = exchange(args, &socket, &upstream, &mut response_buf).await?;
+        if rsp.header.truncation {
+            match exchange_tcp(args, resolver, &upstream).await {
+                Ok(full) => rsp = full,
+                Err(e) => warn!(
+                    "Asking again over TCP for query {} failed, passing on the truncated answer: {:#}",
+                    upstream.header.id, e
+                ),
+            }
+        }
+        rsp.answer
+            .iter()
+            .chain(&rsp.authority)
+            .chain(&rsp.additional)
+            .for_each(|record| debug!("Upstream record: {}", record));
+        // With several questions the first failure wins
+        if rcode == Rcode::NOERROR {
+            rcode = rsp.header.response_code;
+        }
+        authentic_data &= rsp.header.authentic_data;
+        truncation |= rsp.header.truncation;
+        response = response
+            .answers(rsp.answer)
+            .authority(rsp.authority)
+            .additional(rsp.additional);
+    }
+
+    info!("Returning response after resolution finished");
+    Ok(response
+        .rcode(rcode)
+        .authentic_data(authentic_data)
+        .truncation(truncation)
+        .build())
+}
+
+// A fresh socket for every query, bound to a random port so that a spoofed answer has to guess
+// the port as well as the id (RFC 5452 9.2). Being connected, it only receives from the resolver.
+async fn upstream_socket(resolver: SocketAddr) -> Result<UdpSocket> {
+    let ip = match resolver {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let ports: Vec<u16> = (0..8)
+        .map(|_| rand::thread_rng().gen_range(1024..=u16::MAX))
+        .collect();
+    let mut socket = None;
+    for port in ports {
+        match UdpSocket::bind(SocketAddr::new(ip, port)).await {
+            Ok(bound) => {
+                socket = Some(bound);
+                break;
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e).context("Failed binding a socket for the resolver"),
+        }
+    }
+    // Should every pick be taken the OS gets to choose
+    let socket = match socket {
+        Some(socket) => socket,
+        None => UdpSocket::bind(SocketAddr::new(ip, 0))
+            .await
+            .context("Failed binding a socket for the resolver")?,
+    };
+    socket
+        .connect(resolver)
+        .await
+        .context("Failed connecting to the resolver")?;
+    Ok(socket)
+}
+
+// Sends the query again whenever no answer came in time or the socket reported an error, such as
+// a refused port, waiting twice as long after each try.
+async fn exchange(
+    args: &Args,
+    socket: &UdpSocket,
+    query: &Message,
+    buf: &mut [u8],
+) -> Result<Message> {
+    let bytes = query.as_bytes();
+    let mut wait = Duration::from_millis(args.upstream_timeout);
+    let mut last_error = None;
+    for attempt in 1..=args.upstream_retries + 1 {
+        let started = Instant::now();
+        let received = timeout(wait, async {
+            socket.send(&bytes).await.context("Failed sending query")?;
+            recv_response(socket, query, buf).await
+        })
+        .await;
+        match received {
+            Ok(Ok(response)) => return Ok(response),
+            Ok(Err(e)) => {
+                debug!("Attempt {} failed: {:#}", attempt, e);
+                // An error comes back at once, the next try still waits its turn
+                sleep_until(started + wait).await;
+                last_error = Some(e);
+            }
+            Err(_) => debug!("No answer to attempt {} after {:?}", attempt, wait),
+        }
+        wait *= 2;
+    }
+    let error = anyhow!(
+        "No answer from the resolver after {} attempts",
+        args.upstream_retries + 1
+    );
+    Err(match last_error {
+        Some(e) => e.context(error),
+        None => error,
+    })
+}
+
+// Asks once more over a new TCP connection, for answers that didn't fit into UDP.
+async fn exchange_tcp(args: &Args, resolver: SocketAddr, query: &Message) -> Result<Message> {
+    let wait = Duration::from_millis(args.upstream_timeout);
+    let response = timeout(wait, async {
+        let mut stream = TcpStream::connect(resolver)
+            .await
+            .context("Failed connecting to the resolver over TCP")?;
+        let bytes = query.as_bytes();
+        let mut frame = BytesMut::with_capacity(bytes.len() + 2);
+        frame.put_u16(bytes.len() as u16);
+        frame.put_slice(&bytes);
+        stream
+            .write_all(&frame)
+            .await
+            .context("Failed sending query over TCP")?;
+        read_frame(&mut stream)
+            .await
+            .context("Error receiving response over TCP")?
+            .context("The resolver closed the connection without answering")
+    })
+    .await
+    .with_context(|| format!("No answer over TCP within {:?}", wait))??;
+
+    let view = MessageRef::parse(&response).context("Failed parsing the resolver response")?;
+    if !view.is_response_to(query) {
+        bail!("The resolver answered something else over TCP");
+    }
+    view.to_message()
+        .context("Failed parsing the resolver response")
+}
+
+// Waits for the answer to `query`, whatever else arrives is dropped and waiting goes on.
+async fn recv_response(socket: &UdpSocket, query: &Message, buf: &mut [u8]) -> Result<Message> {
+    loop {
+        let len = socket.recv(buf).await.context("Error receiving response")?;
+        match MessageRef::parse(&buf[..len]) {
+            Ok(response) if response.is_response_to(query) => {
+                return response
+                    .to_message()
+                    .context("Failed parsing the resolver response")
+            }
+            Ok(_) => warn!(
+                "Discarding a response from the resolver that doesn't match query {}",
+                query.header.id
+            ),
+            Err(e) => warn!("Discarding a malformed response from the resolver: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::question::Question;
+    use crate::message::types::{QClass, QType};
+    use clap::Parser;
+    use tokio::io::duplex;
+
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = (message.len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    fn query(id: u16, name: &str) -> Vec<u8> {
+        MessageBuilder::new()
+            .id(id)
+            .question(Question {
+                name: name.parse().unwrap(),
+                question_type: QType::A,
+                class: QClass::IN,
+            })
+            .build()
+            .as_bytes()
+    }
+
+    // A resolver that answers everything with an empty response, names starting with "slow"
+    // only after a while.
+    async fn upstream() -> SocketAddr {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; MAX_UDP_PAYLOAD_SIZE as usize];
+            loop {
+                let (len, source) = socket.recv_from(&mut buf).await.unwrap();
+                let query = Message::parse_resolver_response(&buf[..len]).unwrap();
+                let socket = socket.clone();
+                tokio::spawn(async move {
+                    if query.questions[0].name.to_string().starts_with("slow") {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                    }
+                    let response = MessageBuilder::response_to(&query).build().as_bytes();
+                    socket.send_to(&response, source).await.unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    async fn serve_tcp(args: &[&str]) -> SocketAddr {
+        let args = Args::parse_from(["dns"].iter().chain(args));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::new(Server::new(args).await.unwrap()).serve_tcp(listener));
+        addr
+    }
+
+    async fn read_id(stream: &mut TcpStream) -> u16 {
+        let response = read_frame(stream).await.unwrap().unwrap();
+        MessageRef::parse(&response).unwrap();
+        u16::from_be_bytes([response[0], response[1]])
+    }
+
+    #[tokio::test]
+    async fn frames_are_split_on_their_length() {
+        let (mut client, mut server) = duplex(64);
+        let mut stream = frame(b"abc");
+        stream.extend(frame(b""));
+        stream.extend(frame(b"defg"));
+        client.write_all(&stream).await.unwrap();
+        drop(client);
+
+        assert_eq!(
+            read_frame(&mut server).await.unwrap(),
+            Some(b"abc".to_vec())
+        );
+        assert_eq!(read_frame(&mut server).await.unwrap(), Some(vec![]));
+        assert_eq!(
+            read_frame(&mut server).await.unwrap(),
+            Some(b"defg".to_vec())
+        );
+        assert_eq!(read_frame(&mut server).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn closing_inside_a_frame_is_an_error() {
+        for cut_off in [&[0, 10, 1, 2, 3][..], &[0]] {
+            let (mut client, mut server) = duplex(64);
+            client.write_all(cut_off).await.unwrap();
+            drop(client);
+            let e = read_frame(&mut server).await.unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined_queries_are_answered_as_they_finish() {
+        let resolver = format!("--resolver={}", upstream().await);
+        let addr = serve_tcp(&[&resolver]).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let mut queries = frame(&query(1, "slow.example.com"));
+        queries.extend(frame(&query(2, "fast.example.com")));
+        // Garbage in between gets no answer but doesn't end the connection either
+        queries.extend(frame(b""));
+        queries.extend(frame(&query(3, "fast.example.com")));
+        stream.write_all(&queries).await.unwrap();
+
+        let mut ids = vec![];
+        for _ in 0..3 {
+            ids.push(read_id(&mut stream).await);
+        }
+        assert_eq!(ids.pop(), Some(1));
+        ids.sort();
+        assert_eq!(ids, [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn connections_are_limited_and_closed_when_idle() {
+        let addr = serve_tcp(&["--resolver=", "--max-connections=1", "--tcp-idle-timeout=1"]).await;
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        first
+            .write_all(&frame(&query(1, "example.com")))
+            .await
+            .unwrap();
+        assert_eq!(read_id(&mut first).await, 1);
+
+        // Over the limit, closed right away
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(read_frame(&mut second).await.unwrap(), None);
+
+        let idle = timeout(Duration::from_secs(3), read_frame(&mut first)).await;
+        assert_eq!(idle.unwrap().unwrap(), None);
+
+        // The slot is free again once the idle one is gone
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        third
+            .write_all(&frame(&query(3, "example.com")))
+            .await
+            .unwrap();
+        assert_eq!(read_id(&mut third).await, 3);
+    }
+
+    #[tokio::test]
+    async fn refused_queries_are_retried() {
+        // Nothing listens there once the socket is gone, so every try comes back refused
+        let closed = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = closed.local_addr().unwrap();
+        drop(closed);
+        let args = Args::parse_from([
+            "dns",
+            &format!("--resolver={}", resolver),
+            "--upstream-timeout=50",
+            "--upstream-retries=2",
+        ]);
+        let socket = upstream_socket(resolver).await.unwrap();
+        let query = Message::parse_resolver_response(&query(1, "example.com")).unwrap();
+
+        let started = Instant::now();
+        let e = exchange(&args, &socket, &query, &mut [0; 512])
+            .await
+            .unwrap_err();
+        // 50ms, 100ms and 200ms for the three attempts
+        assert!(started.elapsed() >= Duration::from_millis(350));
+        assert!(format!("{:#}", e).contains("after 3 attempts"));
+        assert!(format!("{:#}", e).contains("refused"));
+    }
+}